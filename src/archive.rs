@@ -0,0 +1,431 @@
+//! Read and extract compressed tar archives
+
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, Read},
+    path::{Component, Path, PathBuf},
+};
+
+use indicatif::{ProgressBar, ProgressStyle};
+use tar::{Archive, EntryType};
+
+use crate::error::*;
+
+/// Compression format of tar archive
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    /// Uncompressed tar
+    None,
+    Gzip,
+    Xz,
+    Bzip2,
+    Zstd,
+}
+
+impl Compression {
+    /// Detect by the magic bytes at the head of the file
+    pub fn from_magic(head: &[u8]) -> Option<Self> {
+        if head.starts_with(&[0x1f, 0x8b]) {
+            Some(Compression::Gzip)
+        } else if head.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Compression::Xz)
+        } else if head.starts_with(b"BZh") {
+            Some(Compression::Bzip2)
+        } else if head.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Compression::Zstd)
+        } else if head.get(257..262) == Some(b"ustar") {
+            Some(Compression::None)
+        } else {
+            None
+        }
+    }
+
+    /// Detect by the extension of the file name
+    pub fn from_filename(filename: &str) -> Option<Self> {
+        let table = [
+            (".tar.gz", Compression::Gzip),
+            (".tgz", Compression::Gzip),
+            (".tar.xz", Compression::Xz),
+            (".txz", Compression::Xz),
+            (".tar.bz2", Compression::Bzip2),
+            (".tbz2", Compression::Bzip2),
+            (".tar.zst", Compression::Zstd),
+            (".tzst", Compression::Zstd),
+            (".tar", Compression::None),
+        ];
+        table
+            .iter()
+            .find(|(ext, _)| filename.ends_with(ext))
+            .map(|(_, compression)| *compression)
+    }
+
+    /// Wrap a reader by the decoder of this format
+    pub fn decoder<'a, R: Read + 'a>(self, reader: R) -> io::Result<Box<dyn Read + 'a>> {
+        Ok(match self {
+            Compression::None => Box::new(reader),
+            Compression::Gzip => Box::new(flate2::read::GzDecoder::new(reader)),
+            Compression::Xz => Box::new(xz2::read::XzDecoder::new(reader)),
+            Compression::Bzip2 => Box::new(bzip2::read::BzDecoder::new(reader)),
+            Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(reader)?),
+        })
+    }
+}
+
+/// Open a tar archive, detecting its compression by magic bytes, or by extension if they are unknown.
+pub fn open(path: &Path) -> Result<Archive<Box<dyn Read>>> {
+    let mut head = [0; 512];
+    let mut file = File::open(path).with(path)?;
+    let len = read_head(&mut file, &mut head).with(path)?;
+
+    let filename = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+    let compression = Compression::from_magic(&head[..len])
+        .or_else(|| Compression::from_filename(filename))
+        .ok_or_else(|| Error::UnsupportedArchive { path: path.into() })?;
+    log::debug!("Open {:?} archive: {}", compression, path.display());
+
+    let file = BufReader::new(File::open(path).with(path)?);
+    let reader = compression.decoder(file).with(path)?;
+    Ok(Archive::new(reader))
+}
+
+/// Extract the archive at `path` into `dest`, stripping `strip_components` leading components of each entry.
+/// Entries under the top-level directories of `exclude_dirs` (after stripping) are skipped.
+///
+/// Entries whose path, symlink target or hardlink source escapes `dest` are rejected,
+/// and nothing is written through symlinks extracted earlier.
+pub fn extract(path: &Path, dest: &Path, strip_components: usize, exclude_dirs: &[String]) -> Result<()> {
+    let mut archive = open(path)?;
+    let bar = ProgressBar::new_spinner();
+    bar.set_style(
+        ProgressStyle::default_spinner()
+            .template("{spinner:.green} [{elapsed_precise}] Unpacking: {msg} [{pos}]")?,
+    );
+
+    for entry in archive.entries().with(path)? {
+        let mut entry = entry.with(path)?;
+        let entry_path = entry.path().with(path)?.into_owned();
+        bar.set_message(entry_path.to_string_lossy().to_string());
+        bar.inc(1);
+
+        let rel = match strip_path(&entry_path, strip_components)? {
+            Some(rel) => rel,
+            None => continue,
+        };
+        if is_excluded(&rel, exclude_dirs) {
+            continue;
+        }
+        check_no_symlink(dest, &rel, &entry_path)?;
+        let target = dest.join(&rel);
+
+        match entry.header().entry_type() {
+            EntryType::Directory => {
+                fs::create_dir_all(&target).with(&target)?;
+                continue;
+            }
+            EntryType::Regular | EntryType::Continuous | EntryType::GNUSparse => {}
+            EntryType::Symlink => {
+                let link = entry
+                    .link_name()
+                    .with(path)?
+                    .ok_or_else(|| unsafe_entry(&entry_path, "symlink without target"))?;
+                if link.is_absolute() {
+                    return Err(unsafe_entry(&entry_path, "symlink to absolute path"));
+                }
+                // Symlink target is relative to the directory containing the link
+                let parent = rel.parent().unwrap_or_else(|| Path::new(""));
+                if normalize(&parent.join(&link)).is_none() {
+                    return Err(unsafe_entry(&entry_path, "symlink pointing outside of the destination"));
+                }
+            }
+            EntryType::Link => {
+                let link = entry
+                    .link_name()
+                    .with(path)?
+                    .ok_or_else(|| unsafe_entry(&entry_path, "hardlink without source"))?;
+                let source = strip_path(&link, strip_components)?
+                    .ok_or_else(|| unsafe_entry(&entry_path, "hardlink to stripped path"))?;
+                check_no_symlink(dest, &source, &entry_path)?;
+                let source = dest.join(source);
+                if source == target {
+                    continue;
+                }
+                prepare_target(&target)?;
+                fs::hard_link(&source, &target).with(&target)?;
+                continue;
+            }
+            kind => {
+                log::debug!("Skip {:?} entry: {}", kind, entry_path.display());
+                continue;
+            }
+        }
+
+        prepare_target(&target)?;
+        entry.unpack(&target).with(&target)?;
+    }
+
+    bar.finish_with_message("Unpacking completed");
+    Ok(())
+}
+
+/// Read through the whole archive to check that it is complete, e.g. not truncated by an interrupted download
+pub fn verify(path: &Path) -> Result<()> {
+    let broken = |source| Error::BrokenArchive {
+        path: path.into(),
+        source,
+    };
+    let mut archive = open(path)?;
+    for entry in archive.entries().map_err(broken)? {
+        let mut entry = entry.map_err(broken)?;
+        io::copy(&mut entry, &mut io::sink()).map_err(broken)?;
+    }
+    Ok(())
+}
+
+fn is_excluded(rel: &Path, exclude_dirs: &[String]) -> bool {
+    match rel.components().next() {
+        Some(Component::Normal(top)) => exclude_dirs.iter().any(|dir| top == dir.as_str()),
+        _ => false,
+    }
+}
+
+fn unsafe_entry(entry: &Path, reason: &str) -> Error {
+    Error::UnsafeArchiveEntry {
+        entry: entry.into(),
+        reason: reason.into(),
+    }
+}
+
+/// Normalize a relative path lexically. `None` if it is absolute or escapes by `..`.
+fn normalize(path: &Path) -> Option<PathBuf> {
+    let mut normalized = PathBuf::new();
+    for comp in path.components() {
+        match comp {
+            Component::Normal(name) => normalized.push(name),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    return None;
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(normalized)
+}
+
+/// Path of an entry relative to the destination. `None` if nothing is left after stripping.
+fn strip_path(entry: &Path, strip_components: usize) -> Result<Option<PathBuf>> {
+    if entry
+        .components()
+        .any(|comp| !matches!(comp, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(unsafe_entry(entry, "absolute path or '..' component"));
+    }
+    let rel: PathBuf = entry
+        .components()
+        .filter(|comp| matches!(comp, Component::Normal(_)))
+        .skip(strip_components)
+        .collect();
+    Ok(if rel.as_os_str().is_empty() { None } else { Some(rel) })
+}
+
+/// Reject writing through a symlink already extracted into `dest`
+fn check_no_symlink(dest: &Path, rel: &Path, entry: &Path) -> Result<()> {
+    let mut path = dest.to_path_buf();
+    for ancestor in rel.parent().into_iter().flat_map(|parent| parent.components()) {
+        path.push(ancestor);
+        if fs::symlink_metadata(&path).is_ok_and(|meta| meta.file_type().is_symlink()) {
+            return Err(unsafe_entry(entry, "path through symlink"));
+        }
+    }
+    Ok(())
+}
+
+/// Create the parent directory and remove an existing file to be replaced
+fn prepare_target(target: &Path) -> Result<()> {
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent).with(parent)?;
+    }
+    match fs::symlink_metadata(target) {
+        Ok(meta) if meta.is_dir() => fs::remove_dir_all(target).with(target)?,
+        Ok(_) => fs::remove_file(target).with(target)?,
+        Err(_) => {}
+    }
+    Ok(())
+}
+
+/// Read as many bytes as possible into `buf`, which may be longer than the file
+fn read_head(file: &mut File, buf: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match file.read(&mut buf[len..])? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compression_from_magic() {
+        assert_eq!(Compression::from_magic(&[0x1f, 0x8b, 0x08]), Some(Compression::Gzip));
+        assert_eq!(
+            Compression::from_magic(b"\xfd7zXZ\x00\x00\x04"),
+            Some(Compression::Xz)
+        );
+        assert_eq!(Compression::from_magic(b"BZh91AY"), Some(Compression::Bzip2));
+        assert_eq!(
+            Compression::from_magic(&[0x28, 0xb5, 0x2f, 0xfd, 0x04]),
+            Some(Compression::Zstd)
+        );
+        // compress(1) used by .tar.Z is not supported
+        assert_eq!(Compression::from_magic(&[0x1f, 0x9d, 0x90]), None);
+        assert_eq!(Compression::from_magic(b""), None);
+    }
+
+    #[test]
+    fn test_compression_from_filename() {
+        assert_eq!(
+            Compression::from_filename("llvm-6.0.1.src.tar.xz"),
+            Some(Compression::Xz)
+        );
+        assert_eq!(Compression::from_filename("llvmorg-17.0.2.tar.gz"), Some(Compression::Gzip));
+        assert_eq!(Compression::from_filename("llvm.tbz2"), Some(Compression::Bzip2));
+        assert_eq!(Compression::from_filename("llvm.tar.zst"), Some(Compression::Zstd));
+        assert_eq!(Compression::from_filename("llvm.tar.Z"), None);
+    }
+
+    /// Write an uncompressed tar with raw entry names, which `tar::Builder` refuses for unsafe paths
+    fn write_tar(path: &Path, entries: &[(&str, EntryType, &str)]) -> Result<()> {
+        let mut builder = tar::Builder::new(File::create(path)?);
+        for (name, kind, content) in entries {
+            let mut header = tar::Header::new_old();
+            header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_entry_type(*kind);
+            header.set_mode(0o644);
+            if kind.is_file() {
+                header.set_size(content.len() as u64);
+            } else {
+                header.set_size(0);
+                if !content.is_empty() {
+                    header.as_old_mut().linkname[..content.len()].copy_from_slice(content.as_bytes());
+                }
+            }
+            header.set_cksum();
+            let data = if kind.is_file() { content.as_bytes() } else { &[] };
+            builder.append(&header, data)?;
+        }
+        builder.finish()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        let tar = dir.path().join("src.tar");
+        write_tar(
+            &tar,
+            &[
+                ("top/", EntryType::Directory, ""),
+                ("top/llvm/CMakeLists.txt", EntryType::Regular, "project(LLVM)"),
+                ("top/llvm/link", EntryType::Symlink, "CMakeLists.txt"),
+                ("top/llvm/hard", EntryType::Link, "top/llvm/CMakeLists.txt"),
+            ],
+        )?;
+        let dest = dir.path().join("dest");
+        extract(&tar, &dest, 1, &[])?;
+        assert_eq!(fs::read_to_string(dest.join("llvm/CMakeLists.txt"))?, "project(LLVM)");
+        assert_eq!(fs::read_link(dest.join("llvm/link"))?, Path::new("CMakeLists.txt"));
+        assert_eq!(fs::read_to_string(dest.join("llvm/hard"))?, "project(LLVM)");
+
+        let dest = dir.path().join("dest0");
+        extract(&tar, &dest, 0, &[])?;
+        assert!(dest.join("top/llvm/CMakeLists.txt").is_file());
+
+        let dest = dir.path().join("excluded");
+        extract(&tar, &dest, 1, &["llvm".into()])?;
+        assert!(!dest.join("llvm").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_unsafe() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        let cases: &[&[(&str, EntryType, &str)]] = &[
+            &[("top/../../evil", EntryType::Regular, "x")],
+            &[("/top/evil", EntryType::Regular, "x")],
+            &[("top/link", EntryType::Symlink, "/etc")],
+            &[("top/link", EntryType::Symlink, "../..")],
+            &[("top/hard", EntryType::Link, "top/../../evil")],
+            &[
+                ("top/link", EntryType::Symlink, "."),
+                ("top/link/evil", EntryType::Regular, "x"),
+            ],
+        ];
+        for (i, entries) in cases.iter().enumerate() {
+            let tar = dir.path().join(format!("{}.tar", i));
+            write_tar(&tar, entries)?;
+            let dest = dir.path().join(format!("dest{}", i));
+            match extract(&tar, &dest, 1, &[]) {
+                Err(Error::UnsafeArchiveEntry { .. }) => {}
+                r => panic!("case {} must be rejected: {:?}", i, r),
+            }
+        }
+        assert!(!dir.path().join("evil").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_truncated() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        let mut builder = tar::Builder::new(Vec::new());
+        let data = vec![b'x'; 64 * 1024];
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_cksum();
+        builder.append_data(&mut header, "top/data.txt", &data[..])?;
+        let tar = builder.into_inner()?;
+
+        let path = dir.path().join("archive.tar.gz");
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        io::Write::write_all(&mut encoder, &tar)?;
+        let gz = encoder.finish()?;
+        fs::write(&path, &gz)?;
+        verify(&path)?;
+
+        fs::write(&path, &gz[..gz.len() - 16])?;
+        match verify(&path) {
+            Err(Error::BrokenArchive { .. }) => {}
+            r => panic!("truncated archive must be rejected: {:?}", r),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_by_magic() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        let mut builder = tar::Builder::new(Vec::new());
+        let data = b"hello";
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_cksum();
+        builder.append_data(&mut header, "top/hello.txt", &data[..])?;
+        let tar = builder.into_inner()?;
+
+        // Extension is wrong, but the content is detected
+        let path = dir.path().join("archive.tar.gz");
+        let mut encoder = xz2::write::XzEncoder::new(File::create(&path)?, 6);
+        io::Write::write_all(&mut encoder, &tar)?;
+        encoder.finish()?;
+
+        let mut archive = open(&path)?;
+        let mut entries = archive.entries()?;
+        let entry = entries.next().unwrap()?;
+        assert_eq!(entry.path()?, Path::new("top/hello.txt"));
+        Ok(())
+    }
+}
@@ -3,7 +3,8 @@
 use glob::glob;
 use log::*;
 use regex::Regex;
-use semver::Version;
+use semver::{Version, VersionReq};
+use serde_derive::{Deserialize, Serialize};
 use std::{
     env, fs,
     io::{self, Read, Write},
@@ -11,10 +12,58 @@ use std::{
     process::Command,
 };
 
+use crate::cargo;
 use crate::config::*;
+use crate::entry::{self, BuildType, CMakeGenerator};
 use crate::error::*;
 
 const LLVMENV_FN: &str = ".llvmenv";
+const MANIFEST_FN: &str = "cargo-llvm-manifest.toml";
+
+/// Record of how a build is produced, written into its prefix by `build-entry`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BuildManifest {
+    /// Name of the entry
+    pub entry: String,
+    /// Variant of the build, see `build-entry --variant`
+    pub variant: Option<String>,
+    /// Version of the entry
+    pub version: Option<String>,
+    /// URL of remote entry
+    pub url: Option<String>,
+    /// Source directory of local entry
+    pub path: Option<String>,
+    /// Git commit or SVN revision of the source
+    pub revision: Option<String>,
+    /// SHA-256 checksum of the source tarball
+    pub sha256: Option<String>,
+    /// CMake generator
+    pub generator: CMakeGenerator,
+    /// CMake build type
+    pub build_type: BuildType,
+    /// C++ compiler, e.g. the first line of `c++ --version`
+    pub compiler: String,
+    /// UNIX time when the build is finished
+    pub built_at: u64,
+    /// Enabled sub-projects
+    pub projects: Vec<String>,
+    /// Enabled runtimes
+    pub runtimes: Vec<String>,
+    /// Arguments of cmake configure
+    pub cmake_args: Vec<String>,
+    /// Hash of the entry setting and the source revision, to skip rebuilding the same build
+    pub fingerprint: Option<String>,
+}
+
+impl BuildManifest {
+    pub fn write(&self, prefix: &Path) -> Result<()> {
+        let path = prefix.join(MANIFEST_FN);
+        let content = toml::to_string(self).expect("BuildManifest is always serializable");
+        fs::write(&path, content).with(&path)?;
+        info!("Write build manifest to {}", path.display());
+        Ok(())
+    }
+}
 
 #[derive(Debug)]
 pub struct Build {
@@ -45,6 +94,10 @@ impl Build {
         if name == "system" {
             return Ok(Self::system());
         }
+        // Name is a directory directly under the data directory
+        if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+            return Err(Error::InvalidBuildName { name: name.into() });
+        }
         Ok(Build {
             name: name.into(),
             prefix: data_dir()?.join(name),
@@ -95,6 +148,16 @@ impl Build {
         Ok(())
     }
 
+    /// Manifest written by `build-entry`. None for builds not built by cargo-llvm.
+    pub fn manifest(&self) -> Result<Option<BuildManifest>> {
+        let path = self.prefix.join(MANIFEST_FN);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(&path).with(&path)?;
+        Ok(Some(toml::from_str(&content)?))
+    }
+
     /// Use `llvm-config --version` command
     pub fn version(&self) -> Result<Version> {
         let (stdout, _) = Command::new(self.prefix().join("bin/llvm-config"))
@@ -102,6 +165,59 @@ impl Build {
             .check_output()?;
         parse_version(&stdout)
     }
+
+    /// Environment variables to use this build, e.g. from llvm-sys, clang-sys, and cc
+    pub fn env_vars(&self) -> Result<Vec<(String, String)>> {
+        self.switch_env_vars(None)
+    }
+
+    /// Same as `env_vars`, but `bin/` of the previously used prefix is also removed from PATH
+    pub fn switch_env_vars(&self, previous: Option<&Path>) -> Result<Vec<(String, String)>> {
+        let bin = self.prefix.join("bin");
+        let previous_bin = previous.map(|prefix| prefix.join("bin"));
+        let path = update_path(Some(&bin), previous_bin.as_deref())?;
+
+        let mut vars = vec![
+            ("PATH".to_string(), path),
+            (
+                "LLVM_CONFIG_PATH".to_string(),
+                bin.join("llvm-config").display().to_string(),
+            ),
+            (
+                "LIBCLANG_PATH".to_string(),
+                self.prefix.join("lib").display().to_string(),
+            ),
+            ("CC".to_string(), bin.join("clang").display().to_string()),
+            ("CXX".to_string(), bin.join("clang++").display().to_string()),
+        ];
+        match self.version() {
+            Ok(version) => vars.push((
+                llvm_sys_prefix_var(&version),
+                self.prefix.display().to_string(),
+            )),
+            Err(e) => warn!("Cannot get LLVM version of {}: {}", self.name, e),
+        }
+        Ok(vars)
+    }
+}
+
+/// PATH with `add` prepended and `remove` removed
+pub fn update_path(add: Option<&Path>, remove: Option<&Path>) -> Result<String> {
+    let mut paths: Vec<PathBuf> = add.into_iter().map(Path::to_path_buf).collect();
+    if let Some(path) = env::var_os("PATH") {
+        paths.extend(
+            env::split_paths(&path).filter(|p| Some(p.as_path()) != add && Some(p.as_path()) != remove),
+        );
+    }
+    let path = env::join_paths(paths)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+        .with("PATH")?;
+    Ok(path.to_string_lossy().to_string())
+}
+
+/// Name of the variable llvm-sys reads the prefix from, e.g. `LLVM_SYS_170_PREFIX` for LLVM 17.0
+pub fn llvm_sys_prefix_var(version: &Version) -> String {
+    format!("LLVM_SYS_{}{}_PREFIX", version.major, version.minor)
 }
 
 fn parse_version(version: &str) -> Result<Version> {
@@ -150,13 +266,110 @@ fn load_local_env(path: &Path) -> Result<Option<Build>> {
     }
 }
 
+/// Newest installed build whose version satisfies `req`
+pub fn find_build(req: &VersionReq) -> Result<Option<Build>> {
+    Ok(builds()?
+        .into_iter()
+        .filter_map(|build| Some((build.version().ok()?, build)))
+        .filter(|(version, _)| req.matches(version))
+        .max_by(|(a, _), (b, _)| a.cmp(b))
+        .map(|(_, build)| build))
+}
+
+/// Select build by `[package.metadata.llvm]` in Cargo.toml
+fn load_cargo_metadata(path: &Path) -> Result<Option<Build>> {
+    // A broken Cargo.toml must not break shims and the shell hook
+    let (metadata, manifest) = match cargo::seek_llvm_metadata(path) {
+        Ok(Some(found)) => found,
+        Ok(None) => return Ok(None),
+        Err(e) => {
+            warn!("Ignore LLVM metadata in Cargo.toml of {}: {}", path.display(), e);
+            return Ok(None);
+        }
+    };
+
+    if let Some(name) = metadata.build {
+        let mut build = Build::from_name(&name)?;
+        if !build.exists() {
+            info!("Build it by: cargo-llvm build-entry {}", name);
+            return Err(Error::NoMatchingBuild {
+                requirement: format!("build '{}'", name),
+                manifest,
+            });
+        }
+        build.llvmenv = Some(manifest);
+        return Ok(Some(build));
+    }
+
+    if let Some(version) = metadata.version {
+        let req = VersionReq::parse(&version).map_err(|_| Error::invalid_version(&version))?;
+        if let Some(mut build) = find_build(&req)? {
+            build.llvmenv = Some(manifest);
+            return Ok(Some(build));
+        }
+        if let Some(entry) = entry::find_entry(&req) {
+            info!("Build it by: cargo-llvm build-entry {}", entry.name());
+        }
+        return Err(Error::NoMatchingBuild {
+            requirement: format!("LLVM {}", req),
+            manifest,
+        });
+    }
+    Ok(None)
+}
+
+/// Select build by LLVM version detected from llvm-sys or inkwell dependencies
+fn load_cargo_dependencies(path: &Path) -> Result<Option<Build>> {
+    let (detected, manifest) = match cargo::detect_llvm(path) {
+        Ok(Some(found)) => found,
+        Ok(None) => return Ok(None),
+        Err(e) => {
+            warn!("Ignore dependencies in Cargo.toml/Cargo.lock of {}: {}", path.display(), e);
+            return Ok(None);
+        }
+    };
+    let req = detected.requirement();
+    if let Some(mut build) = find_build(&req)? {
+        build.llvmenv = Some(manifest);
+        return Ok(Some(build));
+    }
+    warn!(
+        "No installed build satisfies LLVM {} required by {}",
+        req, detected.reason
+    );
+    if let Some(entry) = entry::find_entry(&req) {
+        info!("Build it by: cargo-llvm build-entry {}", entry.name());
+    }
+    Ok(None)
+}
+
 fn load_global_env() -> Result<Option<Build>> {
     load_local_env(&config_dir()?)
 }
 
+/// Build set by `cargo-llvm global`
+pub fn global_build() -> Result<Option<Build>> {
+    let build = load_global_env()?.map(|mut build| {
+        build.llvmenv = config_dir().ok().map(|dir| dir.join(LLVMENV_FN));
+        build
+    });
+    Ok(build)
+}
+
+/// Files which [seek_build] may read to select the build, whether they exist or not
+pub fn selection_files() -> Result<Vec<PathBuf>> {
+    let project = project_dir()?;
+    let mut files: Vec<PathBuf> = project
+        .ancestors()
+        .flat_map(|dir| [LLVMENV_FN, "Cargo.toml", "Cargo.lock"].map(|name| dir.join(name)))
+        .collect();
+    files.push(config_dir()?.join(LLVMENV_FN));
+    Ok(files)
+}
+
 pub fn seek_build() -> Result<Build> {
-    // Seek .llvmenv from $PWD
-    let mut path = env::current_dir()?;
+    // Seek .llvmenv from $PWD, or the package root in cargo context
+    let mut path = project_dir()?;
     loop {
         if let Some(mut build) = load_local_env(&path)? {
             build.llvmenv = Some(path.join(LLVMENV_FN));
@@ -167,11 +380,18 @@ pub fn seek_build() -> Result<Build> {
             None => break,
         };
     }
-    // check global setting
+    // check Cargo.toml, then global setting, and then llvm-sys/inkwell dependencies as a fallback
+    let project = project_dir()?;
+    if let Some(build) = load_cargo_metadata(&project)? {
+        return Ok(build);
+    }
     if let Some(mut build) = load_global_env()? {
         build.llvmenv = Some(config_dir()?.join(LLVMENV_FN));
         return Ok(build);
     }
+    if let Some(build) = load_cargo_dependencies(&project)? {
+        return Ok(build);
+    }
     Ok(Build::system())
 }
 
@@ -218,4 +438,31 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_from_name_invalid() {
+        for name in &["", ".", "..", "../other", "17.0.2/bin", "..\\other"] {
+            assert!(
+                matches!(Build::from_name(name), Err(Error::InvalidBuildName { .. })),
+                "{:?} must be rejected",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_broken_cargo_toml() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        fs::write(dir.path().join("Cargo.toml"), "[package\n")?;
+        fs::write(dir.path().join("Cargo.lock"), "[[package]\n")?;
+        assert!(load_cargo_metadata(dir.path())?.is_none());
+        assert!(load_cargo_dependencies(dir.path())?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_llvm_sys_prefix_var() {
+        assert_eq!(llvm_sys_prefix_var(&Version::new(17, 0, 6)), "LLVM_SYS_170_PREFIX");
+        assert_eq!(llvm_sys_prefix_var(&Version::new(3, 9, 1)), "LLVM_SYS_39_PREFIX");
+    }
 }
@@ -0,0 +1,268 @@
+//! Read LLVM requirements of Cargo projects
+//!
+//! A crate can declare which LLVM it uses in Cargo.toml:
+//!
+//! ```toml
+//! [package.metadata.llvm]
+//! version = "^17"     # requirement matched against installed builds
+//! # build = "my-entry" # or the name of a build
+//! ```
+//!
+//! `[workspace.metadata.llvm]` in the workspace root is used if the package does not declare it.
+//!
+//! Without such metadata, the LLVM major version is detected from dependencies:
+//!
+//! - `llvm-sys` resolved in Cargo.lock or declared in Cargo.toml, e.g. `llvm-sys = "170"` is LLVM 17
+//! - `inkwell` features in Cargo.toml, e.g. `features = ["llvm17-0"]` is LLVM 17
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use semver::{Version, VersionReq};
+use serde_derive::Deserialize;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::error::*;
+
+pub const CARGO_TOML: &str = "Cargo.toml";
+pub const CARGO_LOCK: &str = "Cargo.lock";
+
+/// Sections of Cargo.toml declaring dependencies
+const DEPENDENCY_SECTIONS: &[&str] = &["dependencies", "dev-dependencies", "build-dependencies"];
+
+lazy_static! {
+    static ref INKWELL_FEATURE: Regex = Regex::new(r"^llvm(\d+)-(\d+)$").unwrap();
+}
+
+/// `[package.metadata.llvm]` or `[workspace.metadata.llvm]` table
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct LlvmMetadata {
+    /// Requirement of LLVM version, e.g. "^17"
+    pub version: Option<String>,
+
+    /// Name of the build to use
+    pub build: Option<String>,
+}
+
+/// Parse LLVM metadata from Cargo.toml. Package metadata wins over workspace metadata.
+fn parse_llvm_metadata(manifest: &str) -> Result<Option<LlvmMetadata>> {
+    let manifest: toml::Table = toml::from_str(manifest)?;
+    for section in ["package", "workspace"] {
+        let llvm = manifest
+            .get(section)
+            .and_then(|section| section.get("metadata"))
+            .and_then(|metadata| metadata.get("llvm"));
+        if let Some(llvm) = llvm {
+            return Ok(Some(llvm.clone().try_into()?));
+        }
+    }
+    Ok(None)
+}
+
+/// Seek LLVM metadata from Cargo.toml in `dir` and its ancestors,
+/// stopping at the workspace root. Returns the metadata with the path of Cargo.toml declaring it.
+pub fn seek_llvm_metadata(dir: &Path) -> Result<Option<(LlvmMetadata, PathBuf)>> {
+    for dir in dir.ancestors() {
+        let manifest = dir.join(CARGO_TOML);
+        if !manifest.is_file() {
+            continue;
+        }
+        let content = fs::read_to_string(&manifest).with(&manifest)?;
+        if let Some(metadata) = parse_llvm_metadata(&content)? {
+            return Ok(Some((metadata, manifest)));
+        }
+        if content.parse::<toml::Table>()?.contains_key("workspace") {
+            break;
+        }
+    }
+    Ok(None)
+}
+
+/// LLVM major version detected from dependencies
+#[derive(Debug, Clone, PartialEq)]
+pub struct DetectedLlvm {
+    /// LLVM major version
+    pub major: u64,
+
+    /// Human readable reason, e.g. "llvm-sys 170.0.1 in Cargo.lock"
+    pub reason: String,
+}
+
+impl DetectedLlvm {
+    /// Requirement for builds, e.g. `^17`
+    pub fn requirement(&self) -> VersionReq {
+        VersionReq::parse(&format!("^{}", self.major)).unwrap()
+    }
+}
+
+/// LLVM major version of llvm-sys, whose version is `<major><minor>`, e.g. 170 for LLVM 17.0
+fn llvm_sys_major(version: &str) -> Option<u64> {
+    let major: u64 = version
+        .trim_start_matches(['^', '=', '~', ' '])
+        .split('.')
+        .next()?
+        .parse()
+        .ok()?;
+    Some(major / 10)
+}
+
+fn detect_from_lock(lock: &str) -> Result<Option<DetectedLlvm>> {
+    let lock: toml::Table = toml::from_str(lock)?;
+    let packages = match lock.get("package").and_then(toml::Value::as_array) {
+        Some(packages) => packages,
+        None => return Ok(None),
+    };
+    // Several llvm-sys may be resolved, e.g. for different inkwell features. Use the newest one.
+    Ok(packages
+        .iter()
+        .filter(|package| package.get("name").and_then(toml::Value::as_str) == Some("llvm-sys"))
+        .filter_map(|package| package.get("version")?.as_str())
+        .filter_map(|version| Some((Version::parse(version).ok()?, version)))
+        .max_by(|(a, _), (b, _)| a.cmp(b))
+        .and_then(|(_, version)| {
+            Some(DetectedLlvm {
+                major: llvm_sys_major(version)?,
+                reason: format!("llvm-sys {} in {}", version, CARGO_LOCK),
+            })
+        }))
+}
+
+fn detect_from_manifest(manifest: &str) -> Result<Option<DetectedLlvm>> {
+    let manifest: toml::Table = toml::from_str(manifest)?;
+    let workspace = manifest
+        .get("workspace")
+        .and_then(|workspace| workspace.get("dependencies"));
+    let dependencies = DEPENDENCY_SECTIONS
+        .iter()
+        .filter_map(|section| manifest.get(*section))
+        .chain(workspace);
+
+    for deps in dependencies {
+        if let Some(llvm_sys) = deps.get("llvm-sys") {
+            let version = match llvm_sys {
+                toml::Value::String(version) => Some(version.as_str()),
+                dep => dep.get("version").and_then(toml::Value::as_str),
+            };
+            if let Some(major) = version.and_then(llvm_sys_major) {
+                return Ok(Some(DetectedLlvm {
+                    major,
+                    reason: format!("llvm-sys {} in {}", version.unwrap(), CARGO_TOML),
+                }));
+            }
+        }
+        let features = deps
+            .get("inkwell")
+            .and_then(|inkwell| inkwell.get("features"))
+            .and_then(toml::Value::as_array);
+        for feature in features.into_iter().flatten().filter_map(toml::Value::as_str) {
+            if let Some(cap) = INKWELL_FEATURE.captures(feature) {
+                return Ok(Some(DetectedLlvm {
+                    major: cap[1].parse()?,
+                    reason: format!("inkwell feature {} in {}", feature, CARGO_TOML),
+                }));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Detect LLVM major version from Cargo.lock and Cargo.toml in `dir` and its ancestors,
+/// stopping at the workspace root. Returns the detected version with the file declaring it.
+pub fn detect_llvm(dir: &Path) -> Result<Option<(DetectedLlvm, PathBuf)>> {
+    for dir in dir.ancestors() {
+        let lock = dir.join(CARGO_LOCK);
+        if lock.is_file() {
+            if let Some(detected) = detect_from_lock(&fs::read_to_string(&lock).with(&lock)?)? {
+                return Ok(Some((detected, lock)));
+            }
+        }
+        let manifest = dir.join(CARGO_TOML);
+        if !manifest.is_file() {
+            continue;
+        }
+        let content = fs::read_to_string(&manifest).with(&manifest)?;
+        if let Some(detected) = detect_from_manifest(&content)? {
+            return Ok(Some((detected, manifest)));
+        }
+        if content.parse::<toml::Table>()?.contains_key("workspace") {
+            break;
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_llvm_metadata() -> Result<()> {
+        let manifest = r#"
+        [package]
+        name = "my-crate"
+        version = "0.1.0"
+
+        [package.metadata.llvm]
+        version = "^17"
+
+        [workspace.metadata.llvm]
+        build = "my-entry"
+        "#;
+        assert_eq!(
+            parse_llvm_metadata(manifest)?,
+            Some(LlvmMetadata {
+                version: Some("^17".into()),
+                build: None,
+            })
+        );
+
+        let manifest = r#"
+        [workspace]
+        members = ["a", "b"]
+
+        [workspace.metadata.llvm]
+        build = "my-entry"
+        "#;
+        assert_eq!(
+            parse_llvm_metadata(manifest)?.and_then(|m| m.build),
+            Some("my-entry".into())
+        );
+
+        assert_eq!(parse_llvm_metadata("[package]\nname = \"a\"")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_detect_llvm() -> Result<()> {
+        let lock = r#"
+        [[package]]
+        name = "llvm-sys"
+        version = "170.0.1"
+
+        [[package]]
+        name = "llvm-sys"
+        version = "181.1.1"
+        "#;
+        assert_eq!(detect_from_lock(lock)?.map(|d| d.major), Some(18));
+
+        let manifest = r#"
+        [dependencies]
+        llvm-sys = { version = "170", features = ["prefer-dynamic"] }
+        "#;
+        assert_eq!(detect_from_manifest(manifest)?.map(|d| d.major), Some(17));
+
+        let manifest = r#"
+        [dependencies]
+        inkwell = { version = "0.5", features = ["llvm16-0"] }
+        "#;
+        let detected = detect_from_manifest(manifest)?.unwrap();
+        assert_eq!(detected.major, 16);
+        assert!(detected.requirement().matches(&Version::new(16, 0, 6)));
+        assert!(!detected.requirement().matches(&Version::new(17, 0, 0)));
+
+        assert_eq!(detect_from_manifest("[dependencies]\nserde = \"1\"")?, None);
+        Ok(())
+    }
+}
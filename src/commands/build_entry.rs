@@ -10,22 +10,48 @@ pub fn build_entry_command(
     builder: Option<String>,
     nproc: Option<usize>,
     build_type: Option<BuildType>,
+    targets: Option<Vec<String>>,
     skip: bool,
+    force: bool,
+    variant: Option<String>,
+    offline: bool,
 ) -> Result<()> {
-    log::debug!("build_entry_command: name={}, update={}, clean={}, discard={}, builder={:?}, nproc={:?}, build_type={:?}",
-        name, update, clean, discard, builder, nproc, build_type);
+    log::debug!("build_entry_command: name={}, update={}, clean={}, discard={}, builder={:?}, nproc={:?}, build_type={:?}, targets={:?}, force={}, variant={:?}, offline={}",
+        name, update, clean, discard, builder, nproc, build_type, targets, force, variant, offline);
 
     let mut entry = entry::load_entry(&name)?;
+    entry.set_offline(offline);
     let nproc = nproc.unwrap_or_else(num_cpus::get);
     if let Some(builder) = builder {
         entry.set_builder(&builder)?;
     }
+    // Install side-by-side instead of overwriting the build of the entry's own build type and targets
+    let mut derived = Vec::new();
     if let Some(build_type) = build_type {
+        if build_type != entry.build_type() {
+            derived.push(build_type.variant());
+        }
         entry.set_build_type(build_type)?;
     }
+    if let Some(targets) = targets {
+        if entry::targets_variant(&targets) != entry::targets_variant(entry.targets()) {
+            derived.push(entry::targets_variant(&targets));
+        }
+        entry.set_targets(targets)?;
+    }
+    match variant {
+        Some(variant) => entry.set_variant(&variant)?,
+        None if !derived.is_empty() => entry.set_variant(&derived.join("-"))?,
+        None => {}
+    }
     if discard {
         entry.clean_cache_dir()?;
     }
+    // Without update, the source does not change by checkout, and checking before it avoids unpacking an archive again
+    if !force && !update && entry.is_up_to_date()? {
+        log::info!("{} is up to date (use --force to rebuild)", entry.build_name());
+        return Ok(());
+    }
     if !skip {
         entry.checkout()?;
     } else {
@@ -34,6 +60,10 @@ pub fn build_entry_command(
     if update {
         entry.update()?;
     }
+    if !force && update && entry.is_up_to_date()? {
+        log::info!("{} is up to date (use --force to rebuild)", entry.build_name());
+        return Ok(());
+    }
     if clean {
         entry.clean_build_dir()?;
     }
@@ -0,0 +1,267 @@
+use std::{
+    collections::HashSet,
+    fmt, fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use indicatif::{HumanBytes, HumanDuration};
+
+use crate::config::cache_dir;
+use crate::error::{FileIoConvert, Result};
+use crate::{build, entry, resource};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Kind {
+    Tarball,
+    Source,
+    Build,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            Kind::Tarball => "tarball",
+            Kind::Source => "source",
+            Kind::Build => "build",
+        })
+    }
+}
+
+/// Item stored in the cache directory
+#[derive(Debug)]
+struct CacheItem {
+    kind: Kind,
+    path: PathBuf,
+    /// Entry names using this item
+    entries: Vec<String>,
+    /// Variant of the build directory
+    variant: Option<String>,
+    size: u64,
+    /// Newest modification time in the item
+    last_used: SystemTime,
+}
+
+impl CacheItem {
+    fn new(kind: Kind, path: PathBuf, entries: Vec<String>, variant: Option<String>) -> Result<Self> {
+        // Build directories are listed separately, and not counted in the size of the source
+        // tree. They are still counted in its last-used time since a build uses its source.
+        let skip_builds = kind == Kind::Source;
+        let (size, last_used) = disk_usage(&path, skip_builds)?;
+        Ok(CacheItem {
+            kind,
+            path,
+            entries,
+            variant,
+            size,
+            last_used,
+        })
+    }
+
+    fn name(&self) -> String {
+        let name = if self.entries.is_empty() {
+            "(unknown entry)".to_string()
+        } else {
+            self.entries.join(",")
+        };
+        match self.variant {
+            Some(ref variant) => format!("{} ({} variant)", name, variant),
+            None => name,
+        }
+    }
+
+    fn is_installed(&self, installed: &HashSet<(String, Option<String>)>) -> bool {
+        self.entries.iter().any(|entry| match self.kind {
+            Kind::Build => installed.contains(&(entry.clone(), self.variant.clone())),
+            Kind::Tarball | Kind::Source => installed.iter().any(|(e, _)| e == entry),
+        })
+    }
+
+    fn age(&self) -> Duration {
+        SystemTime::now()
+            .duration_since(self.last_used)
+            .unwrap_or_default()
+    }
+
+    fn remove(&self) -> Result<()> {
+        log::info!("Remove {}: {}", self.kind, self.path.display());
+        if self.path.is_dir() {
+            fs::remove_dir_all(&self.path).with(&self.path)?;
+        } else {
+            fs::remove_file(&self.path).with(&self.path)?;
+        }
+        Ok(())
+    }
+}
+
+fn is_build_dir(path: &Path) -> bool {
+    path.is_dir()
+        && path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name == "build" || name.starts_with("build-"))
+}
+
+/// Total size and newest modification time under the path, without following symlinks
+fn disk_usage(path: &Path, skip_builds: bool) -> Result<(u64, SystemTime)> {
+    let meta = fs::symlink_metadata(path).with(path)?;
+    let mut size = meta.len();
+    let mut last_used = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+    if meta.is_dir() {
+        for child in fs::read_dir(path).with(path)? {
+            let child = child.with(path)?.path();
+            let (s, t) = disk_usage(&child, false)?;
+            if !(skip_builds && is_build_dir(&child)) {
+                size += s;
+            }
+            last_used = last_used.max(t);
+        }
+    }
+    Ok((size, last_used))
+}
+
+/// Pairs of entry name and variant which have installed builds
+fn installed_builds() -> Result<HashSet<(String, Option<String>)>> {
+    let mut installed = HashSet::new();
+    for build in build::builds()? {
+        match build.manifest()? {
+            Some(manifest) => installed.insert((manifest.entry, manifest.variant)),
+            None => installed.insert((build.name().to_string(), None)),
+        };
+    }
+    Ok(installed)
+}
+
+/// Collect tarballs, source trees and build directories in the cache directory
+fn collect_items() -> Result<Vec<CacheItem>> {
+    let entries = entry::load_entries().unwrap_or_else(|e| {
+        log::warn!("Cannot load entries, and tarballs are not associated with them: {}", e);
+        Vec::new()
+    });
+    let tarball_dir = resource::tarball_cache_dir()?;
+
+    let mut items = Vec::new();
+    if tarball_dir.exists() {
+        for path in fs::read_dir(&tarball_dir).with(&tarball_dir)? {
+            let path = path.with(&tarball_dir)?.path();
+            let mut users = Vec::new();
+            for entry in &entries {
+                if entry.tarball()?.as_ref() == Some(&path) {
+                    users.push(entry.name().to_string());
+                }
+            }
+            items.push(CacheItem::new(Kind::Tarball, path, users, None)?);
+        }
+    }
+
+    let cache = cache_dir()?;
+    for src in fs::read_dir(&cache).with(&cache)? {
+        let src = src.with(&cache)?.path();
+        if src == tarball_dir || !src.is_dir() {
+            continue;
+        }
+        let name = match src.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+        for build in fs::read_dir(&src).with(&src)? {
+            let build = build.with(&src)?.path();
+            if !is_build_dir(&build) {
+                continue;
+            }
+            let variant = build
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix("build-"))
+                .map(|variant| variant.to_string());
+            items.push(CacheItem::new(Kind::Build, build, vec![name.clone()], variant)?);
+        }
+        items.push(CacheItem::new(Kind::Source, src, vec![name], None)?);
+    }
+    items.sort_by(|a, b| (a.kind, &a.path).cmp(&(b.kind, &b.path)));
+    Ok(items)
+}
+
+pub fn cache_list_command() -> Result<()> {
+    let items = collect_items()?;
+    if items.is_empty() {
+        log::info!("Cache is empty");
+        return Ok(());
+    }
+    let max = items.iter().map(|item| item.name().len()).max().unwrap();
+    for item in &items {
+        println!(
+            "{kind:<7} {name:<width$} {size:>11} {age:>16} ago  {path}",
+            kind = item.kind,
+            name = item.name(),
+            size = HumanBytes(item.size).to_string(),
+            age = HumanDuration(item.age()).to_string(),
+            path = item.path.display(),
+            width = max
+        );
+    }
+    Ok(())
+}
+
+pub fn cache_du_command() -> Result<()> {
+    let items = collect_items()?;
+    let mut total = 0;
+    for kind in &[Kind::Tarball, Kind::Source, Kind::Build] {
+        let (count, size) = items
+            .iter()
+            .filter(|item| item.kind == *kind)
+            .fold((0, 0), |(count, size), item| (count + 1, size + item.size));
+        total += size;
+        println!("{:<8} {:>11} ({} items)", kind, HumanBytes(size).to_string(), count);
+    }
+    println!("{:<8} {:>11}", "total", HumanBytes(total).to_string());
+    Ok(())
+}
+
+/// Remove items matching all given policies
+pub fn cache_prune_command(not_installed: bool, older_than: Option<u64>, dry_run: bool) -> Result<()> {
+    let installed = installed_builds()?;
+    let max_age = older_than.map(|days| Duration::from_secs(days * 24 * 60 * 60));
+
+    let mut removed: Vec<PathBuf> = Vec::new();
+    let mut freed = 0;
+    // Source trees are removed before build directories in them
+    let mut items = collect_items()?;
+    items.sort_by_key(|item| item.kind != Kind::Source);
+    for item in &items {
+        if not_installed && item.is_installed(&installed) {
+            continue;
+        }
+        if max_age.is_some_and(|max_age| item.age() < max_age) {
+            continue;
+        }
+        if removed.iter().any(|dir| item.path.starts_with(dir)) {
+            // Already removed with its source tree
+            freed += item.size;
+            continue;
+        }
+        if dry_run {
+            log::info!("Would remove {}: {}", item.kind, item.path.display());
+        } else {
+            item.remove()?;
+        }
+        freed += item.size;
+        removed.push(item.path.clone());
+    }
+    log::info!(
+        "{} {}",
+        if dry_run { "Would free" } else { "Freed" },
+        HumanBytes(freed)
+    );
+    Ok(())
+}
+
+pub fn cache_clean_command() -> Result<()> {
+    let items = collect_items()?;
+    let freed: u64 = items.iter().map(|item| item.size).sum();
+    for item in items.iter().filter(|item| item.kind != Kind::Build) {
+        item.remove()?;
+    }
+    log::info!("Freed {}", HumanBytes(freed));
+    Ok(())
+}
@@ -0,0 +1,43 @@
+use crate::commands::build_entry::build_entry_command;
+use crate::error::{Error, Result};
+use crate::{build, cargo, config, entry};
+
+pub fn detect_command(build: bool, offline: bool) -> Result<()> {
+    let dir = config::project_dir()?;
+    let (detected, _) = cargo::detect_llvm(&dir)?.ok_or(Error::LlvmNotDetected { path: dir })?;
+    let req = detected.requirement();
+    log::info!("LLVM {} is required by {}", detected.major, detected.reason);
+
+    if let Some(found) = build::find_build(&req)? {
+        log::info!(
+            "Installed build: {} ({})",
+            found.name(),
+            found.prefix().display()
+        );
+        return Ok(());
+    }
+
+    let entry = entry::find_entry(&req).ok_or_else(|| Error::InvalidEntry {
+        name: req.to_string(),
+        message: "Entry not found".into(),
+    })?;
+    if build {
+        build_entry_command(
+            entry.name().into(),
+            false,
+            false,
+            false,
+            None,
+            None,
+            None,
+            None,
+            false,
+            false,
+            None,
+            offline,
+        )
+    } else {
+        log::info!("Build it by: cargo-llvm build-entry {}", entry.name());
+        Ok(())
+    }
+}
@@ -0,0 +1,26 @@
+use std::collections::BTreeMap;
+
+use crate::error::Result;
+use crate::shell::Shell;
+use crate::{build, get_existing_build};
+
+pub fn env_command(name: Option<String>, shell: Option<Shell>, json: bool) -> Result<()> {
+    let build = if let Some(name) = name {
+        get_existing_build(&name)
+    } else {
+        build::seek_build()?
+    };
+    let vars = build.env_vars()?;
+
+    if json {
+        let vars: BTreeMap<_, _> = vars.into_iter().collect();
+        println!("{}", serde_json::to_string_pretty(&vars)?);
+    } else {
+        let shell = shell.unwrap_or_else(Shell::detect);
+        for (key, value) in &vars {
+            println!("{}", shell.export(key, value));
+        }
+    }
+
+    Ok(())
+}
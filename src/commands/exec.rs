@@ -0,0 +1,68 @@
+use std::{
+    ffi::OsStr,
+    process::{exit, Command},
+};
+
+use crate::build::Build;
+use crate::error::{Error, Result};
+use crate::{build, get_existing_build};
+
+pub fn exec_command(name: Option<String>, command: Vec<String>) -> Result<()> {
+    let build = if let Some(name) = name {
+        get_existing_build(&name)
+    } else {
+        build::seek_build()?
+    };
+    let (program, args) = command
+        .split_first()
+        .expect("clap ensures that command is not empty");
+    run_in_build(&build, program, args)
+}
+
+/// Run a command with the environment of the build, and exit with its exit code if it fails
+pub fn run_in_build<S: AsRef<OsStr>>(build: &Build, program: &str, args: &[S]) -> Result<()> {
+    let mut cmd = Command::new(program);
+    cmd.args(args).envs(build.env_vars()?);
+    log::debug!("Running with {}: {:?}", build.name(), cmd);
+
+    let status = cmd.status().map_err(|_| Error::CommandNotFound {
+        cmd: format!("{:?}", cmd),
+    })?;
+    match status.code() {
+        Some(0) => Ok(()),
+        Some(code) => exit(code),
+        None => exit(signal_exit_code(&status)),
+    }
+}
+
+/// Replace this process by a command with the environment of the build
+#[cfg(unix)]
+pub fn exec_in_build<S: AsRef<OsStr>>(build: &Build, program: &str, args: &[S]) -> Result<()> {
+    use std::os::unix::process::CommandExt as _;
+
+    let mut cmd = Command::new(program);
+    cmd.args(args).envs(build.env_vars()?);
+    log::debug!("Exec with {}: {:?}", build.name(), cmd);
+    // `exec` returns only on failure
+    let _ = cmd.exec();
+    Err(Error::CommandNotFound {
+        cmd: format!("{:?}", cmd),
+    })
+}
+
+#[cfg(not(unix))]
+pub fn exec_in_build<S: AsRef<OsStr>>(build: &Build, program: &str, args: &[S]) -> Result<()> {
+    run_in_build(build, program, args)
+}
+
+/// Exit code of a shell when its child is killed by signal
+#[cfg(unix)]
+fn signal_exit_code(status: &std::process::ExitStatus) -> i32 {
+    use std::os::unix::process::ExitStatusExt;
+    128 + status.signal().unwrap_or(0)
+}
+
+#[cfg(not(unix))]
+fn signal_exit_code(_status: &std::process::ExitStatus) -> i32 {
+    1
+}
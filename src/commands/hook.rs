@@ -0,0 +1,134 @@
+use std::{env, fs, path::PathBuf, time::UNIX_EPOCH};
+
+use sha2::{Digest, Sha256};
+
+use crate::build::{self, Build};
+use crate::error::Result;
+use crate::shell::Shell;
+
+/// Build activated by the hook, as `<name>:<path of the file selecting it>`
+const STATE_VAR: &str = "CARGO_LLVM_HOOK_STATE";
+/// Prefix of the build activated by the hook
+const PREFIX_VAR: &str = "CARGO_LLVM_HOOK_PREFIX";
+/// Variables exported by the hook except PATH, separated by `:`
+const VARS_VAR: &str = "CARGO_LLVM_HOOK_VARS";
+/// Hash of $PWD and the files selecting the build when the hook has run last
+const KEY_VAR: &str = "CARGO_LLVM_HOOK_KEY";
+
+/// Print the script installing the hook, e.g. `eval "$(cargo-llvm zsh)"`
+///
+/// The hook runs `hook-env` before every prompt, so that a changed `.llvmenv` is applied without changing directory.
+pub fn hook_command(shell: Shell) -> Result<()> {
+    let exe = env::current_exe()?;
+    let exe = exe.display().to_string().replace('\'', r"'\''");
+    let script = match shell {
+        Shell::Zsh => format!(
+            r#"_cargo_llvm_hook() {{
+  eval "$('{exe}' hook-env --shell zsh)"
+}}
+typeset -ag precmd_functions
+if (( ! ${{precmd_functions[(I)_cargo_llvm_hook]}} )); then
+  precmd_functions=(_cargo_llvm_hook $precmd_functions)
+fi"#
+        ),
+        Shell::Bash => format!(
+            r#"_cargo_llvm_hook() {{
+  local previous_exit_status=$?
+  eval "$('{exe}' hook-env --shell bash)"
+  return $previous_exit_status
+}}
+if [[ ";${{PROMPT_COMMAND[*]:-}};" != *";_cargo_llvm_hook;"* ]]; then
+  PROMPT_COMMAND="_cargo_llvm_hook${{PROMPT_COMMAND:+;$PROMPT_COMMAND}}"
+fi"#
+        ),
+        Shell::Fish => format!(
+            r#"function __cargo_llvm_hook --on-event fish_prompt
+    '{exe}' hook-env --shell fish | source
+end
+__cargo_llvm_hook"#
+        ),
+        Shell::PowerShell => {
+            log::error!("Shell hook is not supported for PowerShell. Use `cargo-llvm env` instead");
+            return Ok(());
+        }
+    };
+    println!("{}", script);
+    Ok(())
+}
+
+/// Print exports switching the environment if the build selected for $PWD has been changed.
+///
+/// This runs on every prompt, and does nothing unless $PWD or a file which may select the build has been changed.
+/// An error is also reported only once until then.
+pub fn hook_env_command(shell: Shell) -> Result<()> {
+    let key = selection_key()?;
+    if env::var(KEY_VAR).ok().as_ref() == Some(&key) {
+        return Ok(());
+    }
+    println!("{}", shell.export(KEY_VAR, &key));
+
+    let build = match build::seek_build() {
+        Ok(build) => build,
+        Err(e) => {
+            eprintln!("cargo-llvm: {}", e);
+            return Ok(());
+        }
+    };
+    // Only builds selected by a file are activated, and otherwise the environment is restored
+    let state = build
+        .env_path()
+        .map(|path| format!("{}:{}", build.name(), path.display()));
+    if state == env::var(STATE_VAR).ok() {
+        return Ok(());
+    }
+
+    let previous = env::var_os(PREFIX_VAR).map(PathBuf::from);
+    for var in env::var(VARS_VAR).unwrap_or_default().split(':') {
+        if !var.is_empty() {
+            println!("{}", shell.unset(var));
+        }
+    }
+
+    match state {
+        Some(state) => activate(shell, &build, previous, &state),
+        None => {
+            if let Some(previous) = previous {
+                let path = build::update_path(None, Some(&previous.join("bin")))?;
+                println!("{}", shell.export("PATH", &path));
+            }
+            for var in [STATE_VAR, PREFIX_VAR, VARS_VAR] {
+                println!("{}", shell.unset(var));
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Hash of $PWD and the modification time and size of the files selecting the build
+fn selection_key() -> Result<String> {
+    let mut hasher = Sha256::new();
+    hasher.update(env::current_dir()?.as_os_str().as_encoded_bytes());
+    for file in build::selection_files()? {
+        if let Ok(meta) = fs::metadata(&file) {
+            let mtime = meta.modified().ok().and_then(|t| t.duration_since(UNIX_EPOCH).ok());
+            hasher.update(format!("{}:{:?}:{}\n", file.display(), mtime, meta.len()));
+        }
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+fn activate(shell: Shell, build: &Build, previous: Option<PathBuf>, state: &str) -> Result<()> {
+    let vars = build.switch_env_vars(previous.as_deref())?;
+    for (key, value) in &vars {
+        println!("{}", shell.export(key, value));
+    }
+    let names: Vec<_> = vars
+        .iter()
+        .map(|(key, _)| key.as_str())
+        .filter(|key| *key != "PATH")
+        .collect();
+    println!("{}", shell.export(STATE_VAR, state));
+    println!("{}", shell.export(PREFIX_VAR, &build.prefix().display().to_string()));
+    println!("{}", shell.export(VARS_VAR, &names.join(":")));
+    Ok(())
+}
@@ -0,0 +1,26 @@
+use crate::error::Result;
+use crate::{build, get_existing_build};
+
+pub fn info_command(name: Option<String>) -> Result<()> {
+    let build = if let Some(name) = name {
+        get_existing_build(&name)
+    } else {
+        build::seek_build()?
+    };
+
+    match build.manifest()? {
+        Some(manifest) => {
+            log::info!("Build {} ({})", build.name(), build.prefix().display());
+            print!(
+                "{}",
+                toml::to_string(&manifest).expect("BuildManifest is always serializable")
+            );
+        }
+        None => log::warn!(
+            "Build {} has no manifest. It is not built by cargo-llvm, or built by an older version",
+            build.name()
+        ),
+    }
+
+    Ok(())
+}
@@ -1,2 +1,10 @@
 pub mod version;
-pub mod build_entry;
\ No newline at end of file
+pub mod build_entry;
+pub mod env;
+pub mod exec;
+pub mod detect;
+pub mod hook;
+pub mod rehash;
+pub mod info;
+pub mod uninstall;
+pub mod cache;
@@ -0,0 +1,129 @@
+use std::{collections::BTreeSet, env, ffi::OsString, fs, path::Path};
+
+use crate::build;
+use crate::commands::exec::exec_in_build;
+use crate::config::shims_dir;
+use crate::error::{Error, FileIoConvert, Result};
+
+/// Write shims for every executable in `bin/` of installed builds
+pub fn rehash_command() -> Result<()> {
+    let shims = shims_dir()?;
+    let exe = env::current_exe()?;
+
+    let mut tools = BTreeSet::new();
+    // system build is not scanned, since /usr/bin contains everything
+    for build in build::builds()?.iter().filter(|b| b.name() != "system") {
+        let bin = build.prefix().join("bin");
+        for entry in fs::read_dir(&bin).with(&bin)? {
+            let path = entry.with(&bin)?.path();
+            if is_executable(&path) {
+                if let Some(name) = path.file_name().and_then(|name| name.to_str()) {
+                    tools.insert(name.to_string());
+                }
+            }
+        }
+    }
+
+    fs::remove_dir_all(&shims).with(&shims)?;
+    fs::create_dir_all(&shims).with(&shims)?;
+    for tool in &tools {
+        write_shim(&shims, tool, &exe)?;
+    }
+
+    log::info!("Wrote {} shims into {}", tools.len(), shims.display());
+    if !env::split_paths(&env::var_os("PATH").unwrap_or_default()).any(|p| p == shims) {
+        log::info!("Add {} to PATH to use them", shims.display());
+    }
+    Ok(())
+}
+
+/// Split arguments of `cargo-llvm shim-exec <tool> -- <args>...` into the tool and its arguments.
+///
+/// Shims are handled before clap, since the arguments of the tool, e.g. `-v` or `--help`, must be passed as-is.
+pub fn parse_shim_args(args: &[OsString]) -> Option<(String, &[OsString])> {
+    if args.get(1).is_none_or(|arg| arg != "shim-exec") {
+        return None;
+    }
+    let tool = args.get(2)?.to_str()?.to_string();
+    let rest = match args.get(3) {
+        Some(sep) if sep == "--" => &args[4..],
+        _ => args.get(3..).unwrap_or_default(),
+    };
+    Some((tool, rest))
+}
+
+/// Run a tool of the build selected for $PWD. This is called from shims.
+pub fn shim_exec_command(tool: String, args: &[OsString]) -> Result<()> {
+    let build = build::seek_build()?;
+    let path = build.prefix().join("bin").join(&tool);
+    if !is_executable(&path) {
+        return Err(Error::ToolNotFound {
+            tool,
+            build: build.name().into(),
+        });
+    }
+    exec_in_build(&build, &path.to_string_lossy(), args)
+}
+
+#[cfg(unix)]
+fn write_shim(shims: &Path, tool: &str, exe: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let shim = shims.join(tool);
+    let exe = exe.display().to_string().replace('\'', r"'\''");
+    let script = format!(
+        "#!/bin/sh\nexec '{}' shim-exec '{}' -- \"$@\"\n",
+        exe,
+        tool.replace('\'', r"'\''")
+    );
+    fs::write(&shim, script).with(&shim)?;
+    fs::set_permissions(&shim, fs::Permissions::from_mode(0o755)).with(&shim)?;
+    Ok(())
+}
+
+#[cfg(windows)]
+fn write_shim(shims: &Path, tool: &str, exe: &Path) -> Result<()> {
+    let tool = tool.trim_end_matches(".exe");
+    let shim = shims.join(format!("{}.cmd", tool));
+    let script = format!("@\"{}\" shim-exec \"{}.exe\" -- %*\r\n", exe.display(), tool);
+    fs::write(&shim, script).with(&shim)?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(windows)]
+fn is_executable(path: &Path) -> bool {
+    path.is_file() && path.extension().is_some_and(|ext| ext == "exe")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_shim_args() {
+        let args = |args: &[&str]| args.iter().map(OsString::from).collect::<Vec<_>>();
+
+        let shim = args(&["cargo-llvm", "shim-exec", "clang", "--", "-v", "--help", "--offline"]);
+        let (tool, rest) = parse_shim_args(&shim).unwrap();
+        assert_eq!(tool, "clang");
+        assert_eq!(rest, &args(&["-v", "--help", "--offline"])[..]);
+
+        // Shims written before `--` was added
+        let shim = args(&["cargo-llvm", "shim-exec", "clang", "-h"]);
+        assert_eq!(parse_shim_args(&shim).unwrap().1, &args(&["-h"])[..]);
+
+        let shim = args(&["cargo-llvm", "shim-exec", "clang", "--", "--"]);
+        assert_eq!(parse_shim_args(&shim).unwrap().1, &args(&["--"])[..]);
+
+        assert!(parse_shim_args(&args(&["cargo-llvm", "-v", "shim-exec", "clang"])).is_none());
+        assert!(parse_shim_args(&args(&["cargo-llvm", "shim-exec"])).is_none());
+    }
+}
@@ -0,0 +1,140 @@
+use std::{fs, path::Path};
+
+use crate::config::data_dir;
+use crate::error::{Error, FileIoConvert, Result};
+use crate::{build, entry, get_existing_build};
+
+pub fn uninstall_command(name: String, force: bool, source: bool, tarball: bool) -> Result<()> {
+    if name == "system" {
+        return Err(Error::CannotUninstall {
+            name,
+            reason: "system build is not managed by cargo-llvm".into(),
+        });
+    }
+    let build = get_existing_build(&name);
+
+    if !force {
+        let in_use = vec![build::global_build()?, build::seek_build().ok()]
+            .into_iter()
+            .flatten()
+            .find(|b| b.name() == build.name());
+        if let Some(in_use) = in_use {
+            let reason = match in_use.env_path() {
+                Some(path) => format!("it is selected by {}", path.display()),
+                None => "it is in use".into(),
+            };
+            return Err(Error::CannotUninstall {
+                name,
+                reason: format!("{} (use --force to remove anyway)", reason),
+            });
+        }
+    }
+
+    // Find the entry of the build to clean up its source and tarball
+    let manifest = build.manifest()?;
+    let entry = if source || tarball {
+        let entry_name = manifest.as_ref().map_or(name.as_str(), |m| m.entry.as_str());
+        match entry::load_entry(entry_name) {
+            Ok(mut entry) => {
+                if let Some(variant) = manifest.as_ref().and_then(|m| m.variant.as_ref()) {
+                    entry.set_variant(variant)?;
+                }
+                Some(entry)
+            }
+            Err(e) => {
+                log::warn!("Cannot find entry of {}, and its source is kept: {}", name, e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let prefix = build.prefix();
+    check_prefix(&name, prefix, &data_dir()?)?;
+    log::info!("Remove build: {}", prefix.display());
+    fs::remove_dir_all(prefix).with(prefix)?;
+
+    if let Some(entry) = entry {
+        if source {
+            // Build directories of the other variants are in the same source tree
+            let others = other_variants(&entry, build.name())?;
+            match entry {
+                // Never remove sources of local entries owned by users
+                entry::Entry::Local { .. } => entry.clean_build_dir()?,
+                entry::Entry::Remote { .. } if !others.is_empty() => {
+                    log::info!("Keep source used by {}", others.join(", "));
+                    entry.clean_build_dir()?;
+                }
+                entry::Entry::Remote { .. } => {
+                    if entry.src_dir()?.exists() {
+                        entry.clean_cache_dir()?;
+                    }
+                }
+            }
+        }
+        if tarball {
+            if let Some(tarball) = entry.tarball()? {
+                if tarball.exists() {
+                    log::info!("Remove tarball: {}", tarball.display());
+                    fs::remove_file(&tarball).with(&tarball)?;
+                }
+            }
+        }
+    }
+
+    log::info!("Run `cargo-llvm rehash` if you use shims");
+    Ok(())
+}
+
+/// Names of the installed builds of the entry except `name`
+fn other_variants(entry: &entry::Entry, name: &str) -> Result<Vec<String>> {
+    let mut others = Vec::new();
+    for build in build::builds()? {
+        if build.name() != name && build.manifest()?.is_some_and(|m| m.entry == entry.name()) {
+            others.push(build.name().to_string());
+        }
+    }
+    Ok(others)
+}
+
+/// Refuse to remove anything except an installed build, i.e. a directory with `bin/` directly under `data_dir`
+fn check_prefix(name: &str, prefix: &Path, data_dir: &Path) -> Result<()> {
+    // `file_name` is None for a path ending with `..`
+    if prefix.file_name().is_none() || prefix.parent() != Some(data_dir) || !prefix.join("bin").is_dir() {
+        return Err(Error::CannotUninstall {
+            name: name.into(),
+            reason: format!("{} is not an installed build", prefix.display()),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_prefix() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        let data_dir = dir.path().join("cargo-llvm");
+        fs::create_dir_all(data_dir.join("17.0.2/bin"))?;
+        fs::create_dir_all(dir.path().join("bin"))?;
+        fs::create_dir_all(data_dir.join("empty"))?;
+        fs::create_dir_all(dir.path().join("other/precious"))?;
+
+        check_prefix("17.0.2", &data_dir.join("17.0.2"), &data_dir)?;
+        for prefix in &[
+            data_dir.join("empty"),
+            data_dir.join(".."),
+            data_dir.clone(),
+            dir.path().join("other"),
+        ] {
+            assert!(matches!(
+                check_prefix("x", prefix, &data_dir),
+                Err(Error::CannotUninstall { .. })
+            ));
+        }
+        Ok(())
+    }
+}
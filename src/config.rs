@@ -1,4 +1,6 @@
 use log::info;
+use serde_derive::Deserialize;
+use std::env;
 use std::fs;
 use std::io::Write;
 use std::path::PathBuf;
@@ -7,6 +9,27 @@ use crate::error::*;
 
 pub const APP_NAME: &str = "cargo-llvm";
 pub const ENTRY_TOML: &str = "entry.toml";
+pub const LOCAL_ENTRY_TOML: &str = ".llvm-entry.toml";
+pub const CONFIG_TOML: &str = "config.toml";
+
+/// Global setting in `$XDG_CONFIG_HOME/cargo-llvm/config.toml`
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Base URLs tried in order before GitHub to download archives of official releases
+    #[serde(default)]
+    pub mirrors: Vec<String>,
+}
+
+/// Load the global setting, which is default if the file does not exist
+pub fn load_config() -> Result<Config> {
+    let path = config_dir()?.join(CONFIG_TOML);
+    if !path.exists() {
+        return Ok(Config::default());
+    }
+    let text = fs::read_to_string(&path).with(&path)?;
+    Ok(toml::from_str(&text)?)
+}
 
 pub fn config_dir() -> Result<PathBuf> {
     let path = dirs::config_dir()
@@ -36,6 +59,32 @@ pub fn data_dir() -> Result<PathBuf> {
     Ok(path)
 }
 
+/// Directory of shims, which should be added to PATH
+pub fn shims_dir() -> Result<PathBuf> {
+    let path = data_dir()?.join("shims");
+    if !path.exists() {
+        fs::create_dir_all(&path).with(&path)?;
+    }
+    Ok(path)
+}
+
+/// Directory where `.llvmenv` and project-local entries are searched from
+///
+/// This is the package root if cargo-llvm runs in cargo context, e.g. as `cargo llvm`, and `$PWD` otherwise.
+pub fn project_dir() -> Result<PathBuf> {
+    if let Some(dir) = env::var_os("CARGO_MANIFEST_DIR") {
+        return Ok(dir.into());
+    }
+    let cwd = env::current_dir()?;
+    // cargo sets $CARGO for external subcommands
+    if env::var_os("CARGO").is_some() {
+        if let Some(root) = cwd.ancestors().find(|dir| dir.join("Cargo.toml").is_file()) {
+            return Ok(root.into());
+        }
+    }
+    Ok(cwd)
+}
+
 /// Initialize configure file
 pub fn init_config() -> Result<()> {
     let dir = config_dir()?;
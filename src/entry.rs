@@ -43,6 +43,80 @@
 //! Entry is regarded as *local* if there is `path` property, and *remote* if there is `url` property.
 //! Other options are common to *remote* entries.
 //!
+//! llvm-project monorepo
+//! ----------------------
+//! For the monorepo, sub-projects and runtimes are selected by `projects` and `runtimes`,
+//! which become `LLVM_ENABLE_PROJECTS` and `LLVM_ENABLE_RUNTIMES`:
+//!
+//! ```toml
+//! [llvm-17]
+//! url      = "https://github.com/llvm/llvm-project/archive/refs/tags/llvmorg-17.0.2.tar.gz"
+//! projects = ["clang", "lld"]
+//! runtimes = ["compiler-rt", "libcxx"]
+//! ```
+//!
+//! cmake is pointed at the `llvm/` sub-directory automatically if the source is a monorepo.
+//! Only the sub-directories needed by `projects` and `runtimes` are checked out from a git repository (as a partial clone)
+//! or extracted from a tar archive. This is enabled by default if `url` is llvm-project, i.e. the repository,
+//! its archive like `llvm-project-17.0.6.src.tar.xz` or the pre-defined releases. Set `sparse = false` to get
+//! the whole monorepo, or `sparse = true` for a mirror of another name.
+//!
+//! Inheritance
+//! ------------
+//! An entry can inherit the setting of another user entry or a pre-defined entry by `extends`.
+//! Keys of the entry win over the inherited ones, and `option` tables are merged:
+//!
+//! ```toml
+//! [my-base]
+//! extends  = "17.0.2"
+//! target   = ["X86"]
+//! projects = ["clang"]
+//!
+//! [my-debug]
+//! extends    = "my-base"
+//! build_type = "Debug"
+//! option     = { LLVM_ENABLE_ASSERTIONS = "ON" }
+//! ```
+//!
+//! An entry inherits the version of a pre-defined release it extends, but is selected only by its own name,
+//! e.g. `build-entry 17.0.2` still builds the pre-defined release.
+//!
+//! Tar archives
+//! -------------
+//! The SHA-256 checksum of a tar archive can be pinned by `sha256` (also for each of `tools`).
+//! It is verified after download and before reusing the cached archive, and a mismatched archive is discarded.
+//! An archive without checksum is read through after download instead, so that a truncated one is not cached.
+//! Pre-defined entries have no built-in checksum, and are verified only in this way unless `sha256` is set
+//! by an entry extending them.
+//!
+//! ```toml
+//! [llvm-17]
+//! url    = "https://example.com/llvm-project-17.0.6.src.tar.xz"
+//! sha256 = "58a8818c60e6627064f312dbf46c02d9949956558340938b71cf731ad8bc0813"
+//! ```
+//!
+//! Tar archives are expected to contain a single top directory, which is stripped when unpacked.
+//! `strip_components` changes the number of stripped leading path components, e.g. `0` for archives without it.
+//!
+//! Git revision
+//! -------------
+//! A git entry checks out the default branch unless one of `rev` (full 40-digit commit SHA), `tag`, or `branch` is set.
+//! Only the revision is fetched shallowly, and its commit SHA is recorded in the manifest of the build.
+//! `build-entry --update` moves the checkout to the latest commit of the branch (or to a changed `rev`/`tag`).
+//!
+//! ```toml
+//! [llvm-pinned]
+//! url = "https://github.com/llvm/llvm-project"
+//! tag = "llvmorg-17.0.6"
+//! ```
+//!
+//! Project-local entries
+//! ----------------------
+//! A repository can ship its own entries in `.llvm-entry.toml`, which is searched from the current directory
+//! (or the package root when invoked as `cargo llvm`) up to the root in the same way as `.llvmenv`. Its format is the same as `entry.toml`.
+//! If an entry of the same name is defined in several places, the project-local entry wins over the global one,
+//! and both win over the pre-defined entries. `cargo-llvm entries` shows where each entry comes from.
+//!
 //! Pre-defined entries
 //! ------------------
 //!
@@ -89,12 +163,30 @@
 //! if you want to use custom settings.
 
 use itertools::*;
-use log::{info, warn};
+use log::info;
 use semver::{Version, VersionReq};
-use serde_derive::Deserialize;
-use std::{collections::HashMap, fs, path::PathBuf, process, str::FromStr};
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    env, fmt, fs,
+    path::{Path, PathBuf},
+    process,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    build::{Build, BuildManifest},
+    config::*,
+    error::*,
+    resource::*,
+};
 
-use crate::{config::*, error::*, resource::*};
+/// Arguments of the last successful cmake configure, stored in the build directory
+const CONFIGURE_ARGS: &str = "cargo-llvm-configure.args";
+/// stdout/stderr of cmake configure, stored in the build directory
+const CONFIGURE_LOG: &str = "cargo-llvm-configure.log";
 
 /// Option for CMake Generators
 ///
@@ -109,7 +201,7 @@ use crate::{config::*, error::*, resource::*};
 /// assert_eq!(CMakeGenerator::from_str("VisualStudio").unwrap(), CMakeGenerator::VisualStudio);
 /// assert!(CMakeGenerator::from_str("MySuperBuilder").is_err());
 /// ```
-#[derive(Deserialize, PartialEq, Debug, Clone, Default)]
+#[derive(Deserialize, Serialize, PartialEq, Debug, Clone, Default)]
 pub enum CMakeGenerator {
     /// Use platform default generator (without -G option)
     #[default] Platform,
@@ -171,7 +263,7 @@ impl CMakeGenerator {
 }
 
 /// CMake build type
-#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
 pub enum BuildType {
     Debug,
     #[default] Release,
@@ -179,6 +271,26 @@ pub enum BuildType {
     MinSizeRel,
 }
 
+impl BuildType {
+    /// Variant name for builds of this type, e.g. "debug"
+    pub fn variant(&self) -> String {
+        format!("{:?}", self).to_ascii_lowercase()
+    }
+}
+
+/// Variant name for builds of the targets regardless of order and case, e.g. "aarch64-x86", or "all" for all targets
+pub fn targets_variant(targets: &[String]) -> String {
+    if targets.is_empty() {
+        return "all".into();
+    }
+    targets
+        .iter()
+        .map(|target| target.to_ascii_lowercase())
+        .sorted()
+        .dedup()
+        .join("-")
+}
+
 impl FromStr for BuildType {
     type Err = Error;
 
@@ -199,14 +311,36 @@ impl FromStr for BuildType {
 /// Setting for both Remote and Local entries. TOML setting file will be decoded into this struct.
 ///
 ///
-#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq)]
 pub struct EntrySetting {
+    /// Name of another entry (user-defined or official) whose setting is inherited
+    pub extends: Option<String>,
+
     /// URL of remote LLVM resource, see also [resouce](../resource/index.html) module
     pub url: Option<String>,
 
     /// Path of local LLVM source dir
     pub path: Option<String>,
 
+    /// SHA-256 checksum of the tar archive at `url`, verified after download and before reusing the cached file
+    pub sha256: Option<String>,
+
+    /// Number of leading path components stripped from the entries of the tar archive at `url` (1 by default)
+    pub strip_components: Option<usize>,
+
+    /// Full commit SHA of the git repository at `url` to be checked out
+    pub rev: Option<String>,
+
+    /// Tag of the git repository at `url` to be checked out
+    pub tag: Option<String>,
+
+    /// Branch of the git repository at `url` to be checked out, instead of `#branch` in the URL
+    pub branch: Option<String>,
+
+    /// Check out only the directories of llvm-project monorepo needed by `projects` and `runtimes`
+    /// (true by default if `url` is llvm-project)
+    pub sparse: Option<bool>,
+
     /// Target to be built, e.g. "X86". Empty means all backend
     #[serde(default)]
     pub target: Vec<String>,
@@ -222,6 +356,231 @@ pub struct EntrySetting {
     /// Additional LLVM build options
     #[serde(default)]
     pub option: HashMap<String, String>,
+
+    /// Additional LLVM tools, e.g. clang, compiler-rt, lld, and so on
+    #[serde(default)]
+    pub tools: Vec<Tool>,
+
+    /// Sub-projects of llvm-project monorepo to be built, e.g. "clang" (`LLVM_ENABLE_PROJECTS`)
+    #[serde(default)]
+    pub projects: Vec<String>,
+
+    /// Runtimes of llvm-project monorepo to be built, e.g. "libcxx" (`LLVM_ENABLE_RUNTIMES`)
+    #[serde(default)]
+    pub runtimes: Vec<String>,
+
+    /// Variant of the build, e.g. "debug", installed side-by-side as `<name>-<variant>`.
+    /// This is set by `build-entry` options, not by TOML.
+    #[serde(skip)]
+    pub variant: Option<String>,
+
+    /// Never access the network, and use only cached or local sources. This is set by `--offline`, not by TOML.
+    #[serde(skip)]
+    pub offline: bool,
+}
+
+/// LLVM tool, e.g. clang, compiler-rt, and so on, checked out into the LLVM source tree
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Tool {
+    /// Name of tool (will be downloaded into `tools/{name}` by default)
+    pub name: String,
+
+    /// URL of the tool. Git/SVN repository or Tar archive are allowed.
+    pub url: String,
+
+    /// Relative path from the top of LLVM source tree
+    /// (see the example of clang-extra in [module level doc](index.html))
+    pub relative_path: Option<String>,
+
+    /// SHA-256 checksum if `url` is a tar archive
+    pub sha256: Option<String>,
+
+    /// Number of leading path components stripped if `url` is a tar archive (1 by default)
+    pub strip_components: Option<usize>,
+}
+
+impl Tool {
+    /// Path of the tool relative to the top of LLVM source tree
+    pub fn rel_path(&self) -> PathBuf {
+        match &self.relative_path {
+            Some(rel_path) => PathBuf::from(rel_path),
+            None => PathBuf::from("tools").join(&self.name),
+        }
+    }
+}
+
+/// Projects accepted by `LLVM_ENABLE_PROJECTS` as `(name, since, until)` major versions
+const KNOWN_PROJECTS: &[(&str, u64, Option<u64>)] = &[
+    ("bolt", 14, None),
+    ("clang", 0, None),
+    ("clang-tools-extra", 0, None),
+    ("compiler-rt", 0, None),
+    ("cross-project-tests", 14, None),
+    ("flang", 11, None),
+    ("libc", 12, None),
+    ("libclc", 0, None),
+    ("libcxx", 0, Some(17)),
+    ("libcxxabi", 0, Some(17)),
+    ("libunwind", 0, Some(17)),
+    ("lld", 0, None),
+    ("lldb", 0, None),
+    ("mlir", 10, None),
+    ("openmp", 0, None),
+    ("polly", 0, None),
+    ("pstl", 0, None),
+];
+
+/// Runtimes accepted by `LLVM_ENABLE_RUNTIMES` as `(name, since, until)` major versions
+const KNOWN_RUNTIMES: &[(&str, u64, Option<u64>)] = &[
+    ("compiler-rt", 0, None),
+    ("flang-rt", 21, None),
+    ("libc", 12, None),
+    ("libcxx", 0, None),
+    ("libcxxabi", 0, None),
+    ("libunwind", 0, None),
+    ("llvm-libgcc", 15, None),
+    ("offload", 19, None),
+    ("openmp", 0, None),
+    ("pstl", 0, None),
+];
+
+/// Other directories of llvm-project monorepo needed to build a project or a runtime
+const COMPONENT_DEPENDENCIES: &[(&str, &[&str])] = &[
+    ("clang-tools-extra", &["clang"]),
+    ("flang", &["clang", "mlir"]),
+    ("flang-rt", &["flang"]),
+    ("libcxx", &["libcxxabi"]),
+    ("libcxxabi", &["libcxx"]),
+    ("lld", &["libunwind"]),
+    ("lldb", &["clang"]),
+    ("llvm-libgcc", &["compiler-rt", "libunwind"]),
+];
+
+/// Top-level directories of llvm-project monorepo which are not needed to build the projects and runtimes.
+///
+/// Only known sub-projects are listed, and other directories, e.g. `llvm/`, `cmake/` and `third-party/`,
+/// are always checked out.
+fn unused_monorepo_dirs(projects: &[String], runtimes: &[String]) -> Vec<String> {
+    let mut used: Vec<&str> = projects.iter().chain(runtimes).map(|c| c.as_str()).collect();
+    if !runtimes.is_empty() {
+        used.push("runtimes");
+    }
+    let mut i = 0;
+    while i < used.len() {
+        if let Some((_, deps)) = COMPONENT_DEPENDENCIES.iter().find(|(name, _)| *name == used[i]) {
+            used.extend(deps.iter().filter(|dep| !used.contains(dep)).collect::<Vec<_>>());
+        }
+        i += 1;
+    }
+    KNOWN_PROJECTS
+        .iter()
+        .chain(KNOWN_RUNTIMES)
+        .map(|(name, _, _)| *name)
+        .chain(std::iter::once("runtimes"))
+        .filter(|name| !used.contains(name))
+        .unique()
+        .map(|name| name.to_string())
+        .sorted()
+        .collect()
+}
+
+/// Commit hash of the local source tree at `path`, with the hash of uncommitted changes if it is dirty,
+/// e.g. `<commit>-dirty.<hash>`. `None` if it is not under git.
+fn local_revision(path: &Path) -> Result<Option<String>> {
+    let head = match git_revision(path) {
+        Some(head) => head,
+        None => return Ok(None),
+    };
+    let git = |args: &[&str]| {
+        process::Command::new("git")
+            .args(args)
+            .current_dir(path)
+            .check_output()
+            .map(|(stdout, _)| stdout)
+    };
+    // Build directories of the entry, i.e. `build/` and `build-<variant>/`, are not the source
+    let pathspec = [".", ":(exclude)build", ":(exclude)build-*"];
+    let diff = git(&[&["diff", "HEAD", "--binary", "--"][..], &pathspec].concat())?;
+    let untracked = git(&[&["ls-files", "--others", "--exclude-standard", "-z", "--"][..], &pathspec].concat())?;
+    if diff.is_empty() && untracked.is_empty() {
+        return Ok(Some(head));
+    }
+
+    let mut hasher = Sha256::new();
+    hasher.update(&diff);
+    for file in untracked.split('\0').filter(|file| !file.is_empty()) {
+        let file_path = path.join(file);
+        hasher.update(file);
+        if file_path.is_file() {
+            hasher.update(fs::read(&file_path).with(&file_path)?);
+        }
+    }
+    Ok(Some(format!("{}-dirty.{:.12x}", head, hasher.finalize())))
+}
+
+/// Whether the URL points llvm-project monorepo, i.e. the repository or its archive,
+/// rather than a split repository like llvm-mirror or `llvm-X.src.tar.xz` whose top is LLVM itself.
+fn is_monorepo_url(url: &str) -> bool {
+    let name = url
+        .trim_end_matches('/')
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default();
+    url.starts_with(OFFICIAL_ARCHIVE_BASE) || name.starts_with("llvm-project")
+}
+
+fn download_options(
+    url: &str,
+    sha256: &Option<String>,
+    strip_components: Option<usize>,
+    offline: bool,
+) -> Result<DownloadOptions> {
+    let default = DownloadOptions::default();
+    // Mirrors serve the archives of official releases
+    let mirrors = if url.starts_with(OFFICIAL_ARCHIVE_BASE) {
+        load_config()?.mirrors
+    } else {
+        Vec::new()
+    };
+    Ok(DownloadOptions {
+        sha256: sha256.clone(),
+        strip_components: strip_components.unwrap_or(default.strip_components),
+        mirrors,
+        offline,
+        ..default
+    })
+}
+
+fn is_commit_sha(s: &str) -> bool {
+    s.len() == 40 && s.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn is_sha256(s: &str) -> bool {
+    s.len() == 64 && s.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Check that every component is known for the version. Any known name is accepted if version is unknown.
+fn check_components(
+    kind: &str,
+    known: &[(&str, u64, Option<u64>)],
+    components: &[String],
+    version: Option<&Version>,
+) -> std::result::Result<(), String> {
+    for component in components {
+        let supported = known.iter().any(|(name, since, until)| {
+            name == component
+                && version.is_none_or(|v| {
+                    v.major >= *since && until.is_none_or(|until| v.major < until)
+                })
+        });
+        if !supported {
+            return Err(match version {
+                Some(version) => format!("Unknown {} '{}' for LLVM {}", kind, component, version),
+                None => format!("Unknown {} '{}'", kind, component),
+            });
+        }
+    }
+    Ok(())
 }
 
 /// Describes how to compile LLVM/Clang
@@ -243,14 +602,123 @@ pub enum Entry {
     },
 }
 
-fn load_entry_toml(toml_str: &str) -> Result<Vec<Entry>> {
-    let entries: HashMap<String, EntrySetting> = toml::from_str(toml_str)?;
-    entries
-        .into_iter()
-        .map(|(name, setting)| Entry::parse_setting(&name, Version::parse(&name).ok(), setting))
+/// Where an entry is defined
+#[derive(Debug, Clone, PartialEq)]
+pub enum EntrySource {
+    /// Pre-defined entry for an official release
+    Official,
+    /// Global entry file, i.e. `$XDG_CONFIG_HOME/cargo-llvm/entry.toml`
+    Global(PathBuf),
+    /// Project-local entry file found by walking up from the current directory
+    Project(PathBuf),
+}
+
+impl fmt::Display for EntrySource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EntrySource::Official => write!(f, "official"),
+            EntrySource::Global(path) => write!(f, "global: {}", path.display()),
+            EntrySource::Project(path) => write!(f, "project: {}", path.display()),
+        }
+    }
+}
+
+fn load_entry_table(table: &toml::Table) -> Result<Vec<Entry>> {
+    table
+        .keys()
+        .map(|name| {
+            let (setting, version) = resolve_setting(table, name, &mut Vec::new())?;
+            let setting: EntrySetting =
+                toml::Value::Table(setting)
+                    .try_into()
+                    .map_err(|e: toml::de::Error| Error::InvalidEntry {
+                        name: name.clone(),
+                        message: e.message().into(),
+                    })?;
+            Entry::parse_setting(name, version, setting)
+        })
         .collect()
 }
 
+/// Resolve the `extends` chain of the entry `name` into a flat TOML table,
+/// together with the version inherited when `name` itself is not a version.
+///
+/// `chain` holds the entries visited so far to detect cyclic inheritance.
+fn resolve_setting(
+    table: &toml::Table,
+    name: &str,
+    chain: &mut Vec<String>,
+) -> Result<(toml::Table, Option<Version>)> {
+    let invalid = |message: String| Error::InvalidEntry {
+        name: name.into(),
+        message,
+    };
+    let setting = table
+        .get(name)
+        .and_then(toml::Value::as_table)
+        .ok_or_else(|| invalid("Entry must be a table".into()))?;
+    let version = Version::parse(name).ok();
+    let parent = match setting.get("extends") {
+        None => return Ok((setting.clone(), version)),
+        Some(toml::Value::String(parent)) => parent,
+        Some(_) => return Err(invalid("`extends` must be a name of entry".into())),
+    };
+
+    chain.push(name.into());
+    // An entry may extend the official release of the same name
+    let (base, base_version) = if parent != name && table.contains_key(parent) {
+        if chain.contains(parent) {
+            return Err(invalid(format!(
+                "Cyclic extends: {} -> {}",
+                chain.join(" -> "),
+                parent
+            )));
+        }
+        resolve_setting(table, parent, chain)?
+    } else if let Some(official) = official_releases().into_iter().find(|e| e.name() == parent) {
+        let base = toml::Table::try_from(official.setting())
+            .map_err(|e| invalid(format!("Cannot inherit '{}': {}", parent, e)))?;
+        (base, official.version().cloned())
+    } else {
+        return Err(invalid(format!("Extends unknown entry '{}'", parent)));
+    };
+    chain.pop();
+
+    Ok((merge_setting(base, setting), version.or(base_version)))
+}
+
+/// Overwrite `base` by the keys of `setting`. `option` tables are merged,
+/// and `url`/`path` are regarded as a single key since only one of them is allowed.
+/// The checksum and `strip_components` of the base are dropped with its `url`,
+/// and `rev`/`tag`/`branch` are also regarded as a single key.
+fn merge_setting(mut base: toml::Table, setting: &toml::Table) -> toml::Table {
+    if setting.contains_key("url") || setting.contains_key("path") {
+        base.remove("url");
+        base.remove("path");
+        base.remove("sha256");
+        base.remove("strip_components");
+    }
+    if ["rev", "tag", "branch"].iter().any(|key| setting.contains_key(*key)) {
+        base.remove("rev");
+        base.remove("tag");
+        base.remove("branch");
+    }
+    for (key, value) in setting {
+        match (base.get_mut(key), value) {
+            (Some(toml::Value::Table(base_option)), toml::Value::Table(option)) if key == "option" => {
+                base_option.extend(option.clone());
+            }
+            _ => {
+                base.insert(key.clone(), value.clone());
+            }
+        }
+    }
+    base
+}
+
+/// Base URL of the archives of official releases
+const OFFICIAL_ARCHIVE_BASE: &str = "https://github.com/llvm/llvm-project/archive/refs/tags/";
+
 pub fn official_releases() -> Vec<Entry> {
     vec![
         Entry::official(18, 1, 0),
@@ -272,26 +740,105 @@ pub fn official_releases() -> Vec<Entry> {
     ]
 }
 
-pub fn load_entries() -> Result<Vec<Entry>> {
-    let global_toml = config_dir()?.join(ENTRY_TOML);
-    let mut entries = load_entry_toml(&fs::read_to_string(&global_toml).with(&global_toml)?)?;
-    let mut official = official_releases();
-    entries.append(&mut official);
+/// Seek the project-local entry file from $PWD, or the package root in cargo context
+fn seek_project_entry_toml() -> Result<Option<PathBuf>> {
+    let mut path = project_dir()?;
+    loop {
+        let cand = path.join(LOCAL_ENTRY_TOML);
+        if cand.is_file() {
+            return Ok(Some(cand));
+        }
+        path = match path.parent() {
+            Some(path) => path.into(),
+            None => return Ok(None),
+        };
+    }
+}
+
+/// Merge entry tables and official releases.
+///
+/// Later tables take precedence over earlier ones, and every user entry over the official release of the same name.
+/// Entries may extend those defined in another table.
+fn merge_entry_tables(tables: Vec<(toml::Table, EntrySource)>) -> Result<Vec<(Entry, EntrySource)>> {
+    let mut merged = toml::Table::new();
+    let mut sources = HashMap::new();
+    for (table, source) in tables {
+        for (name, setting) in table {
+            sources.insert(name.clone(), source.clone());
+            merged.insert(name, setting);
+        }
+    }
+
+    let mut entries: Vec<_> = load_entry_table(&merged)?
+        .into_iter()
+        .map(|entry| {
+            let source = sources[entry.name()].clone();
+            (entry, source)
+        })
+        .collect();
+    entries.extend(
+        official_releases()
+            .into_iter()
+            .filter(|entry| !merged.contains_key(entry.name()))
+            .map(|entry| (entry, EntrySource::Official)),
+    );
     Ok(entries)
 }
 
+/// Load entries with where they are defined.
+///
+/// Precedence: project-local entry file > global entry file > official releases
+pub fn load_entries_with_source() -> Result<Vec<(Entry, EntrySource)>> {
+    let global_toml = config_dir()?.join(ENTRY_TOML);
+    let project_toml = seek_project_entry_toml()?;
+
+    let mut tables = Vec::new();
+    // The global entry file is only optional when there is a project-local one
+    if global_toml.exists() || project_toml.is_none() {
+        let table = toml::from_str(&fs::read_to_string(&global_toml).with(&global_toml)?)?;
+        tables.push((table, EntrySource::Global(global_toml)));
+    }
+    if let Some(project_toml) = project_toml {
+        let table = toml::from_str(&fs::read_to_string(&project_toml).with(&project_toml)?)?;
+        tables.push((table, EntrySource::Project(project_toml)));
+    }
+    merge_entry_tables(tables)
+}
+
+pub fn load_entries() -> Result<Vec<Entry>> {
+    Ok(load_entries_with_source()?
+        .into_iter()
+        .map(|(entry, _)| entry)
+        .collect())
+}
+
+/// Newest entry whose version satisfies `req`. Only official releases are searched if entries cannot be loaded.
+pub fn find_entry(req: &VersionReq) -> Option<Entry> {
+    newest_entry(load_entries().unwrap_or_else(|_| official_releases()), req)
+}
+
+fn newest_entry(entries: Vec<Entry>, req: &VersionReq) -> Option<Entry> {
+    entries
+        .into_iter()
+        .filter(|e| e.named_version().is_some_and(|v| req.matches(v)))
+        .max_by(|a, b| a.version().cmp(&b.version()))
+}
+
 pub fn load_entry(name: &str) -> Result<Entry> {
-    let entries = load_entries()?;
-    for entry in entries {
-        if entry.name() == name {
-            return Ok(entry);
-        }
+    select_entry(load_entries()?, name)
+}
 
-        if let Some(version) = entry.version() {
-            if let Ok(req) = VersionReq::parse(name) {
-                if req.matches(version) {
-                    return Ok(entry);
-                }
+/// Entry of the name, or the first one whose version satisfies `name` as a requirement
+fn select_entry(entries: Vec<Entry>, name: &str) -> Result<Entry> {
+    // An exact name is searched through all entries first, since an entry extending
+    // an official release has its version, e.g. `17.0.2`, but must not be selected by it.
+    if let Some(i) = entries.iter().position(|entry| entry.name() == name) {
+        return Ok(entries.into_iter().nth(i).unwrap());
+    }
+    if let Ok(req) = VersionReq::parse(name) {
+        for entry in entries {
+            if entry.named_version().is_some_and(|version| req.matches(version)) {
+                return Ok(entry);
             }
         }
     }
@@ -301,19 +848,14 @@ pub fn load_entry(name: &str) -> Result<Entry> {
     })
 }
 
-
 impl Entry {
     /// Entry for official LLVM release
     pub fn official(major: u64, minor: u64, patch: u64) -> Self {
         let version = Version::new(major, minor, patch);
-        let mut setting = EntrySetting::default();
-
-        let base_url = format!(
-            "https://github.com/llvm/llvm-project/archive/refs/tags/llvmorg-{}",
-            version
-        );
-
-        setting.url = Some(format!("{}.tar.gz", base_url));
+        let setting = EntrySetting {
+            url: Some(format!("{}llvmorg-{}.tar.gz", OFFICIAL_ARCHIVE_BASE, version)),
+            ..Default::default()
+        };
 
         let name = version.to_string();
         Entry::parse_setting(&name, Some(version), setting).unwrap()
@@ -326,6 +868,38 @@ impl Entry {
                 message: "One of Path or URL are allowed".into(),
             });
         }
+        check_components("project", KNOWN_PROJECTS, &setting.projects, version.as_ref())
+            .and_then(|_| {
+                check_components("runtime", KNOWN_RUNTIMES, &setting.runtimes, version.as_ref())
+            })
+            .map_err(|message| Error::InvalidEntry {
+                name: name.into(),
+                message,
+            })?;
+        if [&setting.rev, &setting.tag, &setting.branch].iter().filter(|r| r.is_some()).count() > 1 {
+            return Err(Error::InvalidEntry {
+                name: name.into(),
+                message: "Only one of rev, tag, or branch is allowed".into(),
+            });
+        }
+        if let Some(rev) = &setting.rev {
+            // An abbreviated SHA cannot be fetched without the whole history
+            if !is_commit_sha(rev) {
+                return Err(Error::InvalidEntry {
+                    name: name.into(),
+                    message: format!("rev must be a full 40-digit commit SHA: {}", rev),
+                });
+            }
+        }
+        let checksums = setting.sha256.iter().chain(setting.tools.iter().filter_map(|t| t.sha256.as_ref()));
+        for sha256 in checksums {
+            if !is_sha256(sha256) {
+                return Err(Error::InvalidEntry {
+                    name: name.into(),
+                    message: format!("Invalid SHA-256 checksum: {}", sha256),
+                });
+            }
+        }
         if let Some(path) = &setting.path {
             return Ok(Entry::Local {
                 name: name.into(),
@@ -375,12 +949,114 @@ impl Entry {
         Ok(())
     }
 
+    pub fn build_type(&self) -> BuildType {
+        self.setting().build_type
+    }
+
+    pub fn set_targets(&mut self, targets: Vec<String>) -> Result<()> {
+        log::info!("Targets: {}", if targets.is_empty() { "all".into() } else { targets.join(";") });
+        self.setting_mut().target = targets;
+        Ok(())
+    }
+
+    pub fn targets(&self) -> &[String] {
+        &self.setting().target
+    }
+
+    pub fn set_variant(&mut self, variant: &str) -> Result<()> {
+        if variant.is_empty() || variant.contains(['/', '\\']) {
+            return Err(Error::InvalidEntry {
+                name: self.name().into(),
+                message: format!("Invalid variant name: '{}'", variant),
+            });
+        }
+        self.setting_mut().variant = Some(variant.into());
+        log::info!("Variant: {}", variant);
+        Ok(())
+    }
+
+    /// Never access the network in checkout and update
+    pub fn set_offline(&mut self, offline: bool) {
+        self.setting_mut().offline = offline;
+    }
+
+    pub fn variant(&self) -> Option<&str> {
+        self.setting().variant.as_deref()
+    }
+
+    /// Name of the installed build, i.e. `<name>-<variant>` for variants
+    pub fn build_name(&self) -> String {
+        match self.variant() {
+            Some(variant) => format!("{}-{}", self.name(), variant),
+            None => self.name().into(),
+        }
+    }
+
+    pub fn tools(&self) -> &[Tool] {
+        &self.setting().tools
+    }
+
+    pub fn projects(&self) -> &[String] {
+        &self.setting().projects
+    }
+
+    pub fn runtimes(&self) -> &[String] {
+        &self.setting().runtimes
+    }
+
+    /// Resource of the main source tree with the revision set by `rev`/`tag`/`branch`
+    fn resource(&self, url: &str) -> Result<Resource> {
+        let setting = self.setting();
+        let reference = match (&setting.rev, &setting.tag, &setting.branch) {
+            (Some(rev), _, _) => Some(GitRef::Rev(rev.clone())),
+            (_, Some(tag), _) => Some(GitRef::Tag(tag.clone())),
+            (_, _, Some(branch)) => Some(GitRef::Branch(branch.clone())),
+            _ => None,
+        };
+        match (Resource::from_url(url, setting.offline)?, reference) {
+            (Resource::Git { url, .. }, Some(reference)) => Ok(Resource::Git {
+                url,
+                reference: Some(reference),
+            }),
+            (_, Some(_)) => Err(Error::InvalidEntry {
+                name: self.name().into(),
+                message: "rev, tag, and branch are allowed only for git repository".into(),
+            }),
+            (src, None) => Ok(src),
+        }
+    }
+
+    /// Resource of the tool and the options to check it out
+    fn tool_resource(&self, tool: &Tool) -> Result<(Resource, DownloadOptions)> {
+        let offline = self.setting().offline;
+        let src = Resource::from_url(&tool.url, offline)?;
+        let opts = download_options(&tool.url, &tool.sha256, tool.strip_components, offline)?;
+        Ok((src, opts))
+    }
+
+    /// Options to check out the main source tree at `url`
+    fn llvm_download_options(&self, url: &str) -> Result<DownloadOptions> {
+        let setting = self.setting();
+        let mut opts = download_options(url, &setting.sha256, setting.strip_components, setting.offline)?;
+        if setting.sparse.unwrap_or_else(|| is_monorepo_url(url)) {
+            opts.exclude_dirs = unused_monorepo_dirs(&setting.projects, &setting.runtimes);
+        }
+        Ok(opts)
+    }
+
     pub fn checkout(&self) -> Result<()> {
         match self {
             Entry::Remote { url, .. } => {
                 log::info!("Checkout LLVM/Clang");
-                let src = Resource::from_url(url)?;
-                src.download(&self.src_dir()?, "llvm".to_string())?;
+                let llvm_dir = self.llvm_dir()?;
+                let src = self.resource(url)?;
+                src.download(&llvm_dir, &self.llvm_download_options(url)?)?;
+
+                for tool in self.tools() {
+                    log::info!("Checkout {}", tool.name);
+                    let (src, opts) = self.tool_resource(tool)?;
+                    src.download(&llvm_dir.join(tool.rel_path()), &opts)?;
+                }
 
                 log::info!("Checkout done");
             }
@@ -389,6 +1065,14 @@ impl Entry {
         Ok(())
     }
 
+    /// Cached archive of tar resource
+    pub fn tarball(&self) -> Result<Option<PathBuf>> {
+        match self {
+            Entry::Remote { url, .. } => cached_tarball(url),
+            Entry::Local { .. } => Ok(None),
+        }
+    }
+
     pub fn clean_cache_dir(&self) -> Result<()> {
         let path = self.src_dir()?;
         info!("Remove cache dir: {}", path.display());
@@ -399,8 +1083,14 @@ impl Entry {
     pub fn update(&self) -> Result<()> {
         match self {
             Entry::Remote { url, .. } => {
-                let src = Resource::from_url(url)?;
-                src.update(&self.src_dir()?)?;
+                let llvm_dir = self.llvm_dir()?;
+                let src = self.resource(url)?;
+                src.update(&llvm_dir, &self.llvm_download_options(url)?)?;
+
+                for tool in self.tools() {
+                    let (src, opts) = self.tool_resource(tool)?;
+                    src.update(&llvm_dir.join(tool.rel_path()), &opts)?;
+                }
             }
             Entry::Local { .. } => {}
         }
@@ -421,6 +1111,12 @@ impl Entry {
         }
     }
 
+    /// Version of the entry named by it, e.g. `17.0.2`. A version inherited by `extends` is not used
+    /// to look up entries by version requirement.
+    fn named_version(&self) -> Option<&Version> {
+        self.version().filter(|version| version.to_string() == self.name())
+    }
+
     pub fn src_dir(&self) -> Result<PathBuf> {
         Ok(match self {
             Entry::Remote { name, .. } => cache_dir()?.join(name),
@@ -428,8 +1124,28 @@ impl Entry {
         })
     }
 
+    /// Top of LLVM source tree, where tools are checked out into
+    pub fn llvm_dir(&self) -> Result<PathBuf> {
+        Ok(match self {
+            Entry::Remote { .. } => self.src_dir()?.join("llvm"),
+            Entry::Local { path, .. } => path.into(),
+        })
+    }
+
+    /// Directory passed to cmake, i.e. `llvm/` of llvm-project monorepo or the top of LLVM source tree
+    pub fn cmake_source_dir(&self) -> Result<PathBuf> {
+        let llvm_dir = self.llvm_dir()?;
+        let monorepo_llvm = llvm_dir.join("llvm");
+        if monorepo_llvm.join("CMakeLists.txt").exists() {
+            Ok(monorepo_llvm)
+        } else {
+            Ok(llvm_dir)
+        }
+    }
+
+    /// Build directory of the variant, which is created if not exists
     pub fn build_dir(&self) -> Result<PathBuf> {
-        let dir = self.src_dir()?.join("build");
+        let dir = self.build_dir_path()?;
         if !dir.exists() {
             info!("Created build dir: {}", dir.display());
             fs::create_dir_all(&dir).with(&dir)?;
@@ -437,15 +1153,25 @@ impl Entry {
         Ok(dir)
     }
 
+    /// Build directory of the variant, i.e. `build/` or `build-<variant>/`. Other variants have their own.
+    fn build_dir_path(&self) -> Result<PathBuf> {
+        Ok(match self.variant() {
+            Some(variant) => self.src_dir()?.join(format!("build-{}", variant)),
+            None => self.src_dir()?.join("build"),
+        })
+    }
+
     pub fn clean_build_dir(&self) -> Result<()> {
-        let path = self.build_dir()?;
-        info!("Remove build dir: {}", path.display());
-        fs::remove_dir_all(&path).with(&path)?;
+        let path = self.build_dir_path()?;
+        if path.exists() {
+            info!("Remove build dir: {}", path.display());
+            fs::remove_dir_all(&path).with(&path)?;
+        }
         Ok(())
     }
 
     pub fn prefix(&self) -> Result<PathBuf> {
-        Ok(data_dir()?.join(self.name()))
+        Ok(data_dir()?.join(self.build_name()))
     }
 
     pub fn build(&self, nproc: usize) -> Result<()> {
@@ -470,13 +1196,101 @@ impl Entry {
 
         cmd.check_run()?;
 
+        self.manifest()?.write(&self.prefix()?)?;
+
         Ok(())
     }
 
-    fn configure(&self) -> Result<()> {
+    /// Manifest recording how the build is produced
+    fn manifest(&self) -> Result<BuildManifest> {
+        let setting = self.setting();
+        let (revision, sha256) = self.source_revision()?;
+        let fingerprint = self.fingerprint(revision.as_deref(), sha256.as_deref())?;
+        let compiler = env::var("CXX").unwrap_or_else(|_| "c++".into());
+        let compiler = process::Command::new(&compiler)
+            .arg("--version")
+            .check_output()
+            .ok()
+            .and_then(|(stdout, _)| stdout.lines().next().map(str::to_string))
+            .unwrap_or(compiler);
+
+        Ok(BuildManifest {
+            entry: self.name().into(),
+            variant: self.variant().map(ToString::to_string),
+            version: self.version().map(ToString::to_string),
+            url: setting.url.clone(),
+            path: setting.path.clone(),
+            revision,
+            sha256,
+            generator: setting.generator.clone(),
+            build_type: setting.build_type,
+            compiler,
+            built_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            projects: setting.projects.clone(),
+            runtimes: setting.runtimes.clone(),
+            cmake_args: self.cmake_args()?,
+            fingerprint: Some(fingerprint),
+        })
+    }
+
+    /// Revision (git commit or svn revision) and tarball checksum of the checked out source.
+    /// Uncommitted changes of a local entry are included in its revision.
+    fn source_revision(&self) -> Result<(Option<String>, Option<String>)> {
+        Ok(match self {
+            Entry::Remote { url, .. } => {
+                let src = self.resource(url)?;
+                let sha256 = match src.tarball()? {
+                    Some(tarball) if tarball.exists() => Some(sha256(&tarball)?),
+                    _ => None,
+                };
+                (src.revision(&self.llvm_dir()?), sha256)
+            }
+            Entry::Local { path, .. } => (local_revision(path)?, None),
+        })
+    }
+
+    /// Hash of the setting and the source revision, which identifies a build
+    fn fingerprint(&self, revision: Option<&str>, sha256: Option<&str>) -> Result<String> {
+        // toml::Table is sorted, and thus the serialized setting is stable
+        let setting = toml::Table::try_from(self.setting()).map_err(|e| Error::InvalidEntry {
+            name: self.name().into(),
+            message: e.to_string(),
+        })?;
+        let mut hasher = Sha256::new();
+        hasher.update(setting.to_string());
+        hasher.update(format!("revision={:?}\nsha256={:?}\n", revision, sha256));
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+
+    /// Whether the same setting and source have been already built and installed
+    pub fn is_up_to_date(&self) -> Result<bool> {
+        let prefix = self.prefix()?;
+        if !prefix.is_dir() {
+            return Ok(false);
+        }
+        let installed = match Build::from_path(&prefix).manifest()? {
+            Some(BuildManifest {
+                fingerprint: Some(fingerprint),
+                ..
+            }) => fingerprint,
+            _ => return Ok(false),
+        };
+        let (revision, sha256) = self.source_revision()?;
+        if revision.is_none() && sha256.is_none() {
+            // Source cannot be identified, e.g. a local tree not under git
+            return Ok(false);
+        }
+        Ok(installed == self.fingerprint(revision.as_deref(), sha256.as_deref())?)
+    }
+
+    /// Arguments of cmake configure
+    fn cmake_args(&self) -> Result<Vec<String>> {
         let setting = self.setting();
         let mut opts = setting.generator.option();
-        opts.push(format!("{}", self.src_dir()?.display()));
+        opts.push(format!("{}", self.cmake_source_dir()?.display()));
 
         opts.push(format!(
             "-DCMAKE_INSTALL_PREFIX={}",
@@ -502,18 +1316,431 @@ impl Entry {
             ));
         }
 
-        // Other options
-        for (k, v) in &setting.option {
+        // Sub-projects and runtimes of llvm-project
+        if !setting.projects.is_empty() {
+            opts.push(format!(
+                "-DLLVM_ENABLE_PROJECTS={}",
+                setting.projects.iter().join(";")
+            ));
+        }
+        if !setting.runtimes.is_empty() {
+            opts.push(format!(
+                "-DLLVM_ENABLE_RUNTIMES={}",
+                setting.runtimes.iter().join(";")
+            ));
+        }
+
+        // Other options, sorted to keep the arguments stable
+        for (k, v) in setting.option.iter().sorted() {
             opts.push(format!("-D{}={}", k, v));
         }
 
+        Ok(opts)
+    }
+
+    fn configure(&self) -> Result<()> {
+        let opts = self.cmake_args()?;
+
+        // Skip if the build directory has been configured with the same arguments
+        let build_dir = self.build_dir()?;
+        let args_cache = build_dir.join(CONFIGURE_ARGS);
+        let args = opts.join("\n");
+        if build_dir.join("CMakeCache.txt").exists()
+            && fs::read_to_string(&args_cache).is_ok_and(|cached| cached == args)
+        {
+            info!("Configuration is up to date");
+            return Ok(());
+        }
+
+        let log_path = build_dir.join(CONFIGURE_LOG);
+        let log = fs::File::create(&log_path).with(&log_path)?;
         let mut cmd = process::Command::new("cmake");
 
         cmd.args(&opts)
-            .current_dir(self.build_dir()?);
+            .current_dir(&build_dir)
+            .stdout(log.try_clone().with(&log_path)?)
+            .stderr(log);
 
         log::debug!("Running: {:#?}", cmd);
+        info!("Configure (log: {})", log_path.display());
+
+        if let Err(e) = cmd.check_run() {
+            log::error!("cmake configure failed. See {}", log_path.display());
+            // Force reconfiguring at next time
+            let _ = fs::remove_file(&args_cache);
+            return Err(e);
+        }
+        fs::write(&args_cache, args).with(&args_cache)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_entry_toml(toml_str: &str) -> Result<Vec<Entry>> {
+        let table: toml::Table = toml::from_str(toml_str)?;
+        load_entry_table(&table)
+    }
+
+    #[test]
+    fn test_load_entry_toml_tools() -> Result<()> {
+        let setting = r#"
+        [llvm-mirror]
+        url    = "https://github.com/llvm-mirror/llvm"
+        target = ["X86"]
+
+        [[llvm-mirror.tools]]
+        name = "clang"
+        url = "https://github.com/llvm-mirror/clang"
+
+        [[llvm-mirror.tools]]
+        name = "clang-extra"
+        url = "https://github.com/llvm-mirror/clang-tools-extra"
+        relative_path = "tools/clang/tools/extra"
+        "#;
+        let entries = load_entry_toml(setting)?;
+        assert_eq!(entries.len(), 1);
+
+        let tools = entries[0].tools();
+        assert_eq!(tools.len(), 2);
+        assert_eq!(tools[0].name, "clang");
+        assert_eq!(tools[0].rel_path(), PathBuf::from("tools/clang"));
+        assert_eq!(tools[1].rel_path(), PathBuf::from("tools/clang/tools/extra"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_entry_toml_projects() -> Result<()> {
+        let setting = r#"
+        ["17.0.2"]
+        url = "https://github.com/llvm/llvm-project/archive/refs/tags/llvmorg-17.0.2.tar.gz"
+        projects = ["clang", "lld"]
+        runtimes = ["compiler-rt", "libcxx"]
+        "#;
+        let entries = load_entry_toml(setting)?;
+        assert_eq!(entries[0].projects(), ["clang", "lld"]);
+        assert_eq!(entries[0].runtimes(), ["compiler-rt", "libcxx"]);
+
+        // libcxx cannot be a project since LLVM 17
+        let setting = r#"
+        ["17.0.2"]
+        url = "https://github.com/llvm/llvm-project/archive/refs/tags/llvmorg-17.0.2.tar.gz"
+        projects = ["libcxx"]
+        "#;
+        assert!(matches!(
+            load_entry_toml(setting).unwrap_err(),
+            Error::InvalidEntry { .. }
+        ));
+
+        // bolt does not exist in LLVM 10
+        let setting = r#"
+        ["10.0.0"]
+        url = "https://github.com/llvm/llvm-project/archive/refs/tags/llvmorg-10.0.0.tar.gz"
+        projects = ["bolt"]
+        "#;
+        assert!(load_entry_toml(setting).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_entry_toml_extends() -> Result<()> {
+        let setting = r#"
+        [my-base]
+        extends = "17.0.2"
+        target = ["X86"]
+        projects = ["clang"]
+        option = { LLVM_ENABLE_ASSERTIONS = "ON", LLVM_PARALLEL_LINK_JOBS = "1" }
+
+        [my-debug]
+        extends = "my-base"
+        build_type = "Debug"
+        option = { LLVM_ENABLE_ASSERTIONS = "OFF" }
+
+        [my-local]
+        extends = "my-debug"
+        path = "/path/to/llvm-project"
+        "#;
+        let entries = load_entry_toml(setting)?;
+        let debug = entries.iter().find(|e| e.name() == "my-debug").unwrap();
+        let Entry::Remote { url, version, setting, .. } = debug else {
+            panic!("my-debug must be a remote entry");
+        };
+        assert_eq!(
+            url,
+            "https://github.com/llvm/llvm-project/archive/refs/tags/llvmorg-17.0.2.tar.gz"
+        );
+        assert_eq!(version, &Some(Version::new(17, 0, 2)));
+        assert_eq!(setting.target, ["X86"]);
+        assert_eq!(setting.projects, ["clang"]);
+        assert_eq!(setting.build_type, BuildType::Debug);
+        assert_eq!(setting.option["LLVM_ENABLE_ASSERTIONS"], "OFF");
+        assert_eq!(setting.option["LLVM_PARALLEL_LINK_JOBS"], "1");
+
+        let local = entries.iter().find(|e| e.name() == "my-local").unwrap();
+        assert!(matches!(local, Entry::Local { .. }));
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_entry_extending_official() -> Result<()> {
+        let table: toml::Table = toml::from_str(
+            r#"
+            [my-debug]
+            extends = "17.0.2"
+            build_type = "Debug"
+            "#,
+        )?;
+        let entries = || -> Result<Vec<Entry>> {
+            let source = EntrySource::Global(PathBuf::from("/global/entry.toml"));
+            let entries = merge_entry_tables(vec![(table.clone(), source)])?;
+            Ok(entries.into_iter().map(|(entry, _)| entry).collect())
+        };
+
+        assert_eq!(select_entry(entries()?, "17.0.2")?.name(), "17.0.2");
+        assert_eq!(select_entry(entries()?, "17")?.name(), "17.0.2");
+        assert_eq!(select_entry(entries()?, "my-debug")?.name(), "my-debug");
+        let req = VersionReq::parse("=17.0.2").unwrap();
+        assert_eq!(newest_entry(entries()?, &req).unwrap().name(), "17.0.2");
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_entry_tables() -> Result<()> {
+        let global: toml::Table = toml::from_str(
+            r#"
+            [llvm-dev]
+            url = "https://github.com/llvm/llvm-project"
+            target = ["X86"]
+
+            ["17.0.2"]
+            extends = "17.0.2"
+            build_type = "Debug"
+            "#,
+        )?;
+        let project: toml::Table = toml::from_str(
+            r#"
+            [llvm-dev]
+            path = "/path/to/llvm-project"
+
+            [my-llvm]
+            extends = "17.0.2"
+            "#,
+        )?;
+        let global_path = PathBuf::from("/global/entry.toml");
+        let project_path = PathBuf::from("/project/.llvm-entry.toml");
+        let entries = merge_entry_tables(vec![
+            (global, EntrySource::Global(global_path.clone())),
+            (project, EntrySource::Project(project_path.clone())),
+        ])?;
+
+        let find = |name: &str| {
+            let found: Vec<_> = entries.iter().filter(|(e, _)| e.name() == name).collect();
+            assert_eq!(found.len(), 1, "{} must be defined once", name);
+            found[0]
+        };
+        let (llvm_dev, source) = find("llvm-dev");
+        assert!(matches!(llvm_dev, Entry::Local { .. }));
+        assert_eq!(source, &EntrySource::Project(project_path.clone()));
+
+        // project entry extends the global one shadowing the official release
+        let (my_llvm, source) = find("my-llvm");
+        assert_eq!(my_llvm.setting().build_type, BuildType::Debug);
+        assert_eq!(source, &EntrySource::Project(project_path));
 
+        let (_, source) = find("17.0.2");
+        assert_eq!(source, &EntrySource::Global(global_path));
+        let (_, source) = find("16.0.6");
+        assert_eq!(source, &EntrySource::Official);
         Ok(())
     }
+
+    #[test]
+    fn test_load_entry_toml_sha256() -> Result<()> {
+        let sha256 = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+        let setting = format!(
+            r#"
+            [pinned]
+            url = "https://example.com/llvm-project.tar.xz"
+            sha256 = "{}"
+
+            [same-archive]
+            extends = "pinned"
+            build_type = "Debug"
+
+            [other-archive]
+            extends = "pinned"
+            url = "https://example.com/other.tar.xz"
+            "#,
+            sha256
+        );
+        let entries = load_entry_toml(&setting)?;
+        let find = |name: &str| entries.iter().find(|e| e.name() == name).unwrap();
+        assert_eq!(find("pinned").setting().sha256.as_deref(), Some(sha256));
+        assert_eq!(find("same-archive").setting().sha256.as_deref(), Some(sha256));
+        assert_eq!(find("other-archive").setting().sha256, None);
+
+        let setting = r#"
+        [broken]
+        url = "https://example.com/llvm-project.tar.xz"
+        sha256 = "not-a-checksum"
+        "#;
+        assert!(matches!(
+            load_entry_toml(setting).unwrap_err(),
+            Error::InvalidEntry { name, .. } if name == "broken"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_entry_toml_git_revision() -> Result<()> {
+        let setting = r#"
+        [llvm-main]
+        url    = "https://github.com/llvm/llvm-project"
+        branch = "main"
+
+        [llvm-pinned]
+        extends = "llvm-main"
+        tag     = "llvmorg-17.0.6"
+        "#;
+        let entries = load_entry_toml(setting)?;
+        let pinned = entries.iter().find(|e| e.name() == "llvm-pinned").unwrap();
+        assert_eq!(pinned.setting().tag.as_deref(), Some("llvmorg-17.0.6"));
+        assert_eq!(pinned.setting().branch, None);
+
+        let setting = r#"
+        [both]
+        url = "https://github.com/llvm/llvm-project"
+        rev = "8db5e66dc9a9cc5e1d0ad0f6b3ad5e1b8c3e9a30"
+        tag = "llvmorg-17.0.6"
+        "#;
+        assert!(matches!(
+            load_entry_toml(setting).unwrap_err(),
+            Error::InvalidEntry { name, .. } if name == "both"
+        ));
+
+        let setting = r#"
+        [abbreviated]
+        url = "https://github.com/llvm/llvm-project"
+        rev = "8db5e66d"
+        "#;
+        assert!(matches!(
+            load_entry_toml(setting).unwrap_err(),
+            Error::InvalidEntry { name, .. } if name == "abbreviated"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_unused_monorepo_dirs() {
+        let unused = unused_monorepo_dirs(&["flang".into()], &["libcxx".into()]);
+        for used in &["flang", "clang", "mlir", "libcxx", "libcxxabi", "runtimes"] {
+            assert!(!unused.iter().any(|dir| dir == used), "{} must be checked out", used);
+        }
+        for dir in &["lld", "lldb", "polly", "compiler-rt", "libunwind"] {
+            assert!(unused.iter().any(|unused| unused == dir), "{} must not be checked out", dir);
+        }
+
+        let unused = unused_monorepo_dirs(&[], &[]);
+        assert!(unused.iter().any(|dir| dir == "runtimes"));
+        assert!(!unused.iter().any(|dir| dir == "llvm" || dir == "cmake" || dir == "third-party"));
+    }
+
+    #[test]
+    fn test_local_revision() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        let path = dir.path();
+        assert_eq!(local_revision(path)?, None);
+
+        fs::write(path.join("CMakeLists.txt"), "project(LLVM)")?;
+        let git = |args: &[&str]| {
+            process::Command::new("git")
+                .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+                .args(args)
+                .current_dir(path)
+                .silent()
+                .check_run()
+        };
+        git(&["init", "-q"])?;
+        git(&["add", "CMakeLists.txt"])?;
+        git(&["commit", "-q", "-m", "init"])?;
+        let head = git_revision(path).unwrap();
+        assert_eq!(local_revision(path)?, Some(head.clone()));
+
+        fs::write(path.join("CMakeLists.txt"), "project(LLVM C CXX)")?;
+        let modified = local_revision(path)?.unwrap();
+        assert!(modified.starts_with(&format!("{}-dirty.", head)));
+        fs::write(path.join("CMakeLists.txt"), "project(LLVM CXX)")?;
+        let modified_again = local_revision(path)?.unwrap();
+        assert_ne!(modified, modified_again);
+
+        fs::write(path.join("new.cpp"), "int main() {}")?;
+        let untracked = local_revision(path)?.unwrap();
+        assert_ne!(untracked, modified_again);
+
+        // In-source build directories are ignored
+        for build in &["build", "build-debug"] {
+            fs::create_dir_all(path.join(build))?;
+            fs::write(path.join(build).join("CMakeCache.txt"), build)?;
+        }
+        assert_eq!(local_revision(path)?.unwrap(), untracked);
+        Ok(())
+    }
+
+    #[test]
+    fn test_targets_variant() {
+        assert_eq!(targets_variant(&["X86".into(), "AArch64".into()]), "aarch64-x86");
+        assert_eq!(targets_variant(&["aarch64".into(), "X86".into(), "x86".into()]), "aarch64-x86");
+        assert_eq!(targets_variant(&[]), "all");
+    }
+
+    #[test]
+    fn test_is_monorepo_url() {
+        for url in &[
+            "https://github.com/llvm/llvm-project",
+            "https://github.com/llvm/llvm-project.git",
+            "https://github.com/llvm/llvm-project/archive/refs/tags/llvmorg-17.0.2.tar.gz",
+            "https://example.com/llvm-project-17.0.6.src.tar.xz",
+            "/mirror/llvm-project/",
+        ] {
+            assert!(is_monorepo_url(url), "{} is monorepo", url);
+        }
+        for url in &[
+            "https://github.com/llvm-mirror/llvm",
+            "https://example.com/llvm-project/llvm-6.0.1.src.tar.xz",
+            "/mirror/llvm",
+        ] {
+            assert!(!is_monorepo_url(url), "{} is not monorepo", url);
+        }
+    }
+
+    #[test]
+    fn test_load_entry_toml_extends_error() {
+        let setting = r#"
+        [a]
+        extends = "b"
+        [b]
+        extends = "c"
+        [c]
+        extends = "a"
+        "#;
+        match load_entry_toml(setting).unwrap_err() {
+            Error::InvalidEntry { name, message } => {
+                assert_eq!(name, "c");
+                assert_eq!(message, "Cyclic extends: a -> b -> c -> a");
+            }
+            e => panic!("Unexpected error: {}", e),
+        }
+
+        let setting = r#"
+        [a]
+        extends = "no-such-entry"
+        "#;
+        assert!(matches!(
+            load_entry_toml(setting).unwrap_err(),
+            Error::InvalidEntry { name, .. } if name == "a"
+        ));
+    }
 }
@@ -23,9 +23,30 @@ pub enum Error {
     #[error("Unsupported cmake build type: {build_type}")]
     UnsupportedBuildType { build_type: String },
 
+    #[error("Unsupported shell: {shell}")]
+    UnsupportedShell { shell: String },
+
     #[error("Config file already exists: {path}")]
     ConfigureAlreadyExists { path: PathBuf },
 
+    #[error("No installed build satisfies {requirement} required by {manifest}")]
+    NoMatchingBuild {
+        requirement: String,
+        manifest: PathBuf,
+    },
+
+    #[error("Cannot detect LLVM version from llvm-sys or inkwell in {path}")]
+    LlvmNotDetected { path: PathBuf },
+
+    #[error("{tool} is not found in build {build}")]
+    ToolNotFound { tool: String, build: String },
+
+    #[error("Invalid build name: '{name}'")]
+    InvalidBuildName { name: String },
+
+    #[error("Cannot uninstall {name}: {reason}")]
+    CannotUninstall { name: String, reason: String },
+
     #[error("Failed to get LLVM version: {version}")]
     InvalidVersion { version: String },
 
@@ -38,9 +59,37 @@ pub enum Error {
         source: toml::de::Error,
     },
 
+    #[error(transparent)]
+    InvalidJSON {
+        #[from]
+        source: serde_json::Error,
+    },
+
     #[error("Entry {name} is invalid: {message}")]
     InvalidEntry { name: String, message: String },
 
+    #[error("Checksum mismatch of {path}: expected sha256 {expected}, but got {actual}")]
+    ChecksumMismatch {
+        path: PathBuf,
+        expected: String,
+        actual: String,
+    },
+
+    #[error("Unsupported archive format: {path}")]
+    UnsupportedArchive { path: PathBuf },
+
+    #[error("Broken archive {path}: {source}")]
+    BrokenArchive { path: PathBuf, source: io::Error },
+
+    #[error("Unsafe entry {entry} in archive: {reason}")]
+    UnsafeArchiveEntry { entry: PathBuf, reason: String },
+
+    #[error("Revision {rev} is not found in {url}")]
+    GitRevisionNotFound { url: String, rev: String },
+
+    #[error("Network access to {url} is disabled in offline mode")]
+    Offline { url: String },
+
     #[error("HTTP request does not succeed with {status}: {url}")]
     HttpError {
         url: String,
@@ -1,19 +1,30 @@
+pub mod archive;
 pub mod build;
+pub mod cargo;
 pub mod config;
 pub mod entry;
 pub mod error;
 pub mod resource;
+pub mod shell;
 pub mod commands;
 
 use std::{
     env,
+    ffi::OsString,
     path::PathBuf,
     process::{exit, Command},
 };
-use clap::{Parser, Subcommand, builder::{styling, Styles}};
-use log::log;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand, builder::{styling, Styles}};
 use vit_logger::{VitLogger, Config as VitConfig};
 use crate::commands::build_entry::build_entry_command;
+use crate::commands::cache::{cache_clean_command, cache_du_command, cache_list_command, cache_prune_command};
+use crate::commands::detect::detect_command;
+use crate::commands::env::env_command;
+use crate::commands::exec::exec_command;
+use crate::commands::hook::{hook_command, hook_env_command};
+use crate::commands::info::info_command;
+use crate::commands::rehash::{parse_shim_args, rehash_command, shim_exec_command};
+use crate::commands::uninstall::uninstall_command;
 use crate::commands::version::version_command;
 use crate::error::CommandExt;
 
@@ -32,6 +43,9 @@ struct Program {
     #[arg(global = true, short, long)]
     verbose: bool,
 
+    #[arg(global = true, long, help = "Never access the network, and use only cached or local sources")]
+    offline: bool,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -76,6 +90,40 @@ enum Commands {
             help = "Overwrite cmake build type (Debug, Release, RelWithDebInfo, or MinSizeRel)"
         )]
         build_type: Option<entry::BuildType>,
+        #[arg(short = 'f', long = "force", help = "Rebuild even if the same build is installed")]
+        force: bool,
+        #[arg(
+            long = "target",
+            value_delimiter = ',',
+            help = "Overwrite LLVM targets to be built, e.g. X86,AArch64"
+        )]
+        targets: Option<Vec<String>>,
+        #[arg(
+            long = "variant",
+            help = "Install as <name>-<variant> side-by-side. Derived from --build-type and --target if they differ from the entry"
+        )]
+        variant: Option<String>,
+    },
+
+    #[command(name = "uninstall", visible_alias = "remove", about = "Remove a build")]
+    Uninstall {
+        name: String,
+        #[arg(short = 'f', long = "force", help = "Remove even if the build is selected by global or .llvmenv")]
+        force: bool,
+        #[arg(
+            short = 's',
+            long = "source",
+            help = "Also remove source of remote entry (or build directory of local entry)"
+        )]
+        source: bool,
+        #[arg(short = 't', long = "tarball", help = "Also remove cached tarball")]
+        tarball: bool,
+    },
+
+    #[command(name = "cache", about = "Manage downloaded tarballs, source trees and build directories")]
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommands,
     },
 
     #[command(name = "current", about = "Show the name of current build")]
@@ -96,6 +144,45 @@ enum Commands {
         patch: bool,
     },
 
+    #[command(name = "info", about = "Show how the build is produced")]
+    Info { name: Option<String> },
+
+    #[command(name = "env", about = "Print environment variables to use the current build")]
+    Env {
+        #[arg(short = 'n', long = "name")]
+        name: Option<String>,
+        #[arg(
+            short = 's',
+            long = "shell",
+            help = "Shell syntax (bash, zsh, fish, or powershell). Detected from $SHELL by default"
+        )]
+        shell: Option<shell::Shell>,
+        #[arg(long = "json", conflicts_with = "shell", help = "Print as JSON")]
+        json: bool,
+    },
+
+    #[command(name = "exec", about = "Run a command with the environment of the current build")]
+    Exec {
+        #[arg(short = 'n', long = "name")]
+        name: Option<String>,
+        #[arg(
+            required = true,
+            trailing_var_arg = true,
+            allow_hyphen_values = true,
+            value_name = "COMMAND"
+        )]
+        command: Vec<String>,
+    },
+
+    #[command(
+        name = "detect",
+        about = "Detect LLVM version required by llvm-sys or inkwell in Cargo.toml/Cargo.lock"
+    )]
+    Detect {
+        #[arg(short = 'b', long = "build", help = "Build the newest matching entry if not installed")]
+        build: bool,
+    },
+
     #[command(name = "global", about = "Set the build to use (global)")]
     Global { name: String },
 
@@ -120,15 +207,94 @@ enum Commands {
     #[command(name = "edit", about = "Edit cargo-llvm configure in your editor")]
     Edit {},
 
-    #[command(name = "zsh", about = "Setup Zsh integration")]
+    #[command(name = "zsh", about = "Setup Zsh integration, e.g. eval \"$(cargo-llvm zsh)\"")]
     Zsh {},
+
+    #[command(name = "bash", about = "Setup Bash integration, e.g. eval \"$(cargo-llvm bash)\"")]
+    Bash {},
+
+    #[command(name = "fish", about = "Setup Fish integration, e.g. cargo-llvm fish | source")]
+    Fish {},
+
+    #[command(name = "rehash", about = "Write shims of executables in installed builds")]
+    Rehash {},
+
+    #[command(name = "hook-env", hide = true)]
+    HookEnv {
+        #[arg(short = 's', long = "shell")]
+        shell: shell::Shell,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum CacheCommands {
+    #[command(name = "list", about = "List cached items with their size and last-used time")]
+    List {},
+
+    #[command(name = "du", about = "Show disk usage of the cache")]
+    Du {},
+
+    #[command(
+        name = "prune",
+        about = "Remove cached items matching all given policies",
+        group = clap::ArgGroup::new("policy").required(true).multiple(true)
+    )]
+    Prune {
+        #[arg(long = "not-installed", group = "policy", help = "Remove items of entries without an installed build")]
+        not_installed: bool,
+        #[arg(long = "older-than", value_name = "DAYS", group = "policy", help = "Remove items unused for DAYS days")]
+        older_than: Option<u64>,
+        #[arg(short = 'n', long = "dry-run", help = "Only show what would be removed")]
+        dry_run: bool,
+    },
+
+    #[command(name = "clean", about = "Remove everything in the cache")]
+    Clean {},
+}
+
+/// Parse arguments, accepting both `cargo-llvm <command>` and `cargo llvm <command>`
+///
+/// cargo invokes external subcommands as `cargo-llvm llvm <command>`.
+fn parse_program() -> Program {
+    let mut args: Vec<OsString> = env::args_os().collect();
+    let mut cmd = Program::command();
+    if args.get(1).is_some_and(|arg| arg == "llvm") {
+        args.remove(1);
+        cmd = cmd.bin_name("cargo llvm");
+    }
+    Program::from_arg_matches(&cmd.get_matches_from(args)).unwrap_or_else(|e| e.exit())
 }
 
 fn main() -> error::Result<()> {
-    let opt = Program::parse();
+    let args: Vec<OsString> = env::args_os().collect();
+    if let Some((tool, args)) = parse_shim_args(&args) {
+        // Called from a shim. Nothing is logged not to be mixed into the output of the tool.
+        if let Err(e) = shim_exec_command(tool, args) {
+            eprintln!("cargo-llvm: {}", e);
+            exit(1);
+        }
+        return Ok(());
+    }
+
+    let opt = parse_program();
     let verbose = opt.verbose;
+    let offline = opt.offline || resource::is_offline_env();
+    // Output of these commands is evaluated by shell, and logs must not be mixed into it
+    let quiet = matches!(
+        opt.command,
+        Commands::Env { .. } | Commands::HookEnv { .. }
+    );
 
-    std::env::set_var("RUST_LOG", if verbose { "trace" } else { "info" });
+    std::env::set_var(
+        "RUST_LOG",
+        if quiet {
+            "off"
+        } else if verbose {
+            "trace"
+        } else {
+            "info"
+        },
+    );
     VitLogger::new().init(
         VitConfig::builder()
             .text(true)
@@ -148,10 +314,18 @@ fn main() -> error::Result<()> {
             let max = builds.iter().map(|b| b.name().len()).max().unwrap();
             log::info!("Builds:");
             for b in &builds {
+                let variant = match b.manifest() {
+                    Ok(Some(manifest)) => manifest
+                        .variant
+                        .as_ref()
+                        .map(|variant| format!(" ({} variant of {})", variant, manifest.entry)),
+                    _ => None,
+                };
                 println!(
-                    "{name:<width$}: {prefix}",
+                    "{name:<width$}: {prefix}{variant}",
                     name = b.name(),
                     prefix = b.prefix().display(),
+                    variant = variant.unwrap_or_default(),
                     width = max
                 );
             }
@@ -160,11 +334,31 @@ fn main() -> error::Result<()> {
         }
 
         Commands::Entries {} => {
-            if let Ok(entries) = entry::load_entries() {
+            if let Ok(entries) = entry::load_entries_with_source() {
                 log::info!("Entries:");
 
-                for entry in &entries {
-                    println!("     - {}", entry.name());
+                let max = entries.iter().map(|(e, _)| e.name().len()).max().unwrap_or(0);
+                for (entry, source) in &entries {
+                    println!(
+                        "     - {name:<width$} ({source})",
+                        name = entry.name(),
+                        source = source,
+                        width = max
+                    );
+                    if !entry.projects().is_empty() {
+                        println!("         projects: {}", entry.projects().join(", "));
+                    }
+                    if !entry.runtimes().is_empty() {
+                        println!("         runtimes: {}", entry.runtimes().join(", "));
+                    }
+                    for tool in entry.tools() {
+                        println!(
+                            "         + {} ({}) -> {}",
+                            tool.name,
+                            tool.url,
+                            tool.rel_path().display()
+                        );
+                    }
                 }
             } else {
                 panic!("No entries. Please define entries in $XDG_CONFIG_HOME/cargo-llvm/entry.toml");
@@ -182,7 +376,41 @@ fn main() -> error::Result<()> {
             skip_download,
             nproc,
             build_type,
-        } => build_entry_command(name, update, clean, discard, builder, nproc, build_type, skip_download),
+            targets,
+            force,
+            variant,
+        } => build_entry_command(
+            name,
+            update,
+            clean,
+            discard,
+            builder,
+            nproc,
+            build_type,
+            targets,
+            skip_download,
+            force,
+            variant,
+            offline,
+        ),
+
+        Commands::Uninstall {
+            name,
+            force,
+            source,
+            tarball,
+        } => uninstall_command(name, force, source, tarball),
+
+        Commands::Cache { command } => match command {
+            CacheCommands::List {} => cache_list_command(),
+            CacheCommands::Du {} => cache_du_command(),
+            CacheCommands::Prune {
+                not_installed,
+                older_than,
+                dry_run,
+            } => cache_prune_command(not_installed, older_than, dry_run),
+            CacheCommands::Clean {} => cache_clean_command(),
+        },
 
         Commands::Current => {
             let build = build::seek_build()?;
@@ -213,13 +441,20 @@ fn main() -> error::Result<()> {
             minor,
             patch,
         } => version_command(name, major, minor, patch),
+        Commands::Info { name } => info_command(name),
+        Commands::Env { name, shell, json } => env_command(name, shell, json),
+        Commands::Exec { name, command } => exec_command(name, command),
+        Commands::Detect { build } => detect_command(build, offline),
         Commands::Global { name } => {
             let build = get_existing_build(&name);
             build.set_global()
         }
         Commands::Local { name, path } => {
             let build = get_existing_build(&name);
-            let path = path.unwrap_or_else(|| env::current_dir().unwrap());
+            let path = match path {
+                Some(path) => path,
+                None => config::project_dir()?,
+            };
             build.set_local(&path)
         }
         Commands::Archive { name } => {
@@ -238,10 +473,11 @@ fn main() -> error::Result<()> {
                 .arg(config::config_dir()?.join(config::ENTRY_TOML))
                 .check_run()
         }
-        _ => {
-            eprintln!("Subcommand not implemented");
-            exit(1);
-        }
+        Commands::Zsh {} => hook_command(shell::Shell::Zsh),
+        Commands::Bash {} => hook_command(shell::Shell::Bash),
+        Commands::Fish {} => hook_command(shell::Shell::Fish),
+        Commands::HookEnv { shell } => hook_env_command(shell),
+        Commands::Rehash {} => rehash_command(),
     };
 
     match result {
@@ -249,7 +485,11 @@ fn main() -> error::Result<()> {
             log::debug!("Done");
         }
         Err(e) => {
-            log::error!("{}", e);
+            if quiet {
+                eprintln!("cargo-llvm: {}", e);
+            } else {
+                log::error!("{}", e);
+            }
             exit(1);
         }
     }
@@ -258,7 +498,13 @@ fn main() -> error::Result<()> {
 }
 
 fn get_existing_build(name: &str) -> build::Build {
-    let build = build::Build::from_name(name).unwrap();
+    let build = match build::Build::from_name(name) {
+        Ok(build) => build,
+        Err(e) => {
+            log::error!("{}", e);
+            exit(1)
+        }
+    };
     if build.exists() {
         build
     } else {
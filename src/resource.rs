@@ -1,28 +1,103 @@
 //! Get remote LLVM/Clang source
 
-use futures::{
-    executor::{block_on_stream, BlockingStream},
-    Stream,
-};
 use indicatif::{ProgressBar, ProgressStyle};
 use log::*;
-use std::{fs, io, path::*, process::Command};
+use std::{env, fmt, fs, io, io::Write, path::*, process::Command, thread, time::Duration};
 use std::fs::File;
-use std::io::{Read, Write};
-use flate2::read::GzDecoder;
-use tar::Archive;
+use sha2::{Digest, Sha256};
 use tempfile::TempDir;
 use url::Url;
+use crate::archive;
 use crate::config::cache_dir;
 use crate::error::*;
 
+/// Environment variable to disable network access like `--offline`
+pub const OFFLINE_ENV: &str = "CARGO_LLVM_OFFLINE";
+
+/// File recording how a tar archive has been extracted, written into the destination
+const EXTRACTED_MARKER: &str = ".cargo-llvm-extracted";
+
+/// Number of retries of a download on transient errors
+const DOWNLOAD_RETRIES: u32 = 5;
+
+/// Extensions of tar archives
+const ARCHIVE_EXTENSIONS: &[&str] = &[
+    ".tar.gz", ".tar.xz", ".tar.bz2", ".tar.zst", ".tar.Z", ".tgz", ".txz", ".tbz2", ".tzst", ".taz",
+];
+
+/// Options of [Resource::download], which are used only for tar archive
+#[derive(Debug, Clone, PartialEq)]
+pub struct DownloadOptions {
+    /// Expected SHA-256 checksum of the archive
+    pub sha256: Option<String>,
+    /// Number of leading path components stripped from archive entries
+    pub strip_components: usize,
+    /// Base URLs tried in order before the URL of the resource. They must serve the archive of the same file name.
+    pub mirrors: Vec<String>,
+    /// Top-level directories not to be checked out or extracted. Git repository is cloned partially in this case.
+    pub exclude_dirs: Vec<String>,
+    /// Never access the network, and use only cached or local sources
+    pub offline: bool,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        DownloadOptions {
+            sha256: None,
+            // Archives usually contain a single top directory, e.g. `llvm-project-llvmorg-17.0.2/`
+            strip_components: 1,
+            mirrors: Vec::new(),
+            exclude_dirs: Vec::new(),
+            offline: false,
+        }
+    }
+}
+
+/// Revision of git repository to be checked out
+#[derive(Debug, Clone, PartialEq)]
+pub enum GitRef {
+    Branch(String),
+    Tag(String),
+    /// Full commit SHA
+    Rev(String),
+}
+
+impl GitRef {
+    fn refspec(&self) -> String {
+        match self {
+            GitRef::Branch(branch) => format!("+refs/heads/{0}:refs/remotes/origin/{0}", branch),
+            GitRef::Tag(tag) => format!("+refs/tags/{0}:refs/tags/{0}", tag),
+            GitRef::Rev(rev) => rev.clone(),
+        }
+    }
+
+    /// Whether the commit `head` is this revision. Branches are moved only by update.
+    fn is_checked_out(&self, dest: &Path, head: &str) -> bool {
+        match self {
+            GitRef::Branch(_) => true,
+            GitRef::Tag(tag) => git_resolve(dest, &format!("refs/tags/{}", tag)).as_deref() == Some(head),
+            GitRef::Rev(rev) => head.eq_ignore_ascii_case(rev),
+        }
+    }
+}
+
+impl fmt::Display for GitRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GitRef::Branch(branch) => write!(f, "branch {}", branch),
+            GitRef::Tag(tag) => write!(f, "tag {}", tag),
+            GitRef::Rev(rev) => write!(f, "rev {}", rev),
+        }
+    }
+}
+
 /// Remote LLVM/Clang resource
 #[derive(Debug, PartialEq)]
 pub enum Resource {
     /// Remote Subversion repository
     Svn { url: String },
     /// Remote Git repository
-    Git { url: String, branch: Option<String> },
+    Git { url: String, reference: Option<GitRef> },
     /// Tar archive
     Tar { url: String },
 }
@@ -30,12 +105,14 @@ pub enum Resource {
 impl Resource {
     /// Detect remote resorce from URL
     ///
+    /// An URL which cannot be detected without git access is an error in `offline` mode.
+    ///
     /// - Official subversion repository
     ///
     /// ```
     /// # use llvmenv::resource::Resource;
     /// let llvm_official_url = "http://llvm.org/svn/llvm-project/llvm/trunk";
-    /// let svn = Resource::from_url(llvm_official_url).unwrap();
+    /// let svn = Resource::from_url(llvm_official_url, false).unwrap();
     /// assert_eq!(svn, Resource::Svn { url: llvm_official_url.into() });
     /// ```
     ///
@@ -44,8 +121,8 @@ impl Resource {
     /// ```
     /// # use llvmenv::resource::Resource;
     /// let github_mirror = "https://github.com/llvm/llvm-project";
-    /// let git = Resource::from_url(github_mirror).unwrap();
-    /// assert_eq!(git, Resource::Git { url: github_mirror.into(), branch: None });
+    /// let git = Resource::from_url(github_mirror, false).unwrap();
+    /// assert_eq!(git, Resource::Git { url: github_mirror.into(), reference: None });
     /// ```
     ///
     /// - Tar Archive
@@ -53,13 +130,41 @@ impl Resource {
     /// ```
     /// # use llvmenv::resource::Resource;
     /// let tar_url = "http://releases.llvm.org/6.0.1/llvm-6.0.1.src.tar.xz";
-    /// let tar = Resource::from_url(tar_url).unwrap();
+    /// let tar = Resource::from_url(tar_url, false).unwrap();
     /// assert_eq!(tar, Resource::Tar { url: tar_url.into() });
     /// ```
-    pub fn from_url(url_str: &str) -> Result<Self> {
+    pub fn from_url(url_str: &str, offline: bool) -> Result<Self> {
+        // Local archive or git mirror
+        if let Some(path) = local_path(url_str) {
+            if !path.exists() {
+                return Err(io::Error::new(io::ErrorKind::NotFound, "Neither a URL nor an existing path"))
+                    .with(path);
+            }
+            let file_url = Url::from_file_path(&path).map_err(|_| Error::InvalidUrl {
+                url: url_str.into(),
+            })?;
+            let filename = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+            if ARCHIVE_EXTENSIONS.iter().any(|ext| filename.ends_with(ext)) {
+                debug!("Local archive: {}", path.display());
+                return Ok(Resource::Tar {
+                    url: file_url.into(),
+                });
+            }
+            // Either a working tree or a bare repository, e.g. `git clone --mirror`
+            if path.join(".git").exists() || (path.join("HEAD").is_file() && path.join("objects").is_dir()) {
+                debug!("Local git repository: {}", path.display());
+                return Ok(Resource::Git {
+                    url: file_url.into(),
+                    reference: get_branch_from_url(url_str).unwrap_or(None),
+                });
+            }
+            return Err(io::Error::new(io::ErrorKind::NotFound, "Neither an archive nor a git repository"))
+                .with(path);
+        }
+
         // Check file extension
         if let Ok(filename) = get_filename_from_url(url_str) {
-            for ext in &[".tar.gz", ".tar.xz", ".tar.bz2", ".tar.Z", ".tgz", ".taz"] {
+            for ext in ARCHIVE_EXTENSIONS {
                 if filename.ends_with(ext) {
                     debug!("Find archive extension '{}' at the end of URL", ext);
                     return Ok(Resource::Tar {
@@ -79,7 +184,7 @@ impl Resource {
                 debug!("Find '.git' extension");
                 return Ok(Resource::Git {
                     url: strip_branch_from_url(url_str)?,
-                    branch: get_branch_from_url(url_str)?,
+                    reference: get_branch_from_url(url_str)?,
                 });
             }
         }
@@ -93,7 +198,7 @@ impl Resource {
                 debug!("URL is a cloud git service: {}", service);
                 return Ok(Resource::Git {
                     url: strip_branch_from_url(url_str)?,
-                    branch: get_branch_from_url(url_str)?,
+                    reference: get_branch_from_url(url_str)?,
                 });
             }
         }
@@ -109,13 +214,16 @@ impl Resource {
                 debug!("URL is LLVM Git repository");
                 return Ok(Resource::Git {
                     url: strip_branch_from_url(url_str)?,
-                    branch: get_branch_from_url(url_str)?,
+                    reference: get_branch_from_url(url_str)?,
                 });
             }
         }
 
         // Try access with git
         //
+        // This needs network, and the URL must be explicit in offline mode
+        check_online(url_str, offline)?;
+        //
         // - SVN repository cannot handle git access
         // - Some Git service (e.g. GitHub) *can* handle svn access
         //
@@ -147,7 +255,7 @@ impl Resource {
                 debug!("Git access succeeds");
                 Ok(Resource::Git {
                     url: strip_branch_from_url(url_str)?,
-                    branch: get_branch_from_url(url_str)?,
+                    reference: get_branch_from_url(url_str)?,
                 })
             }
             Err(_) => {
@@ -159,167 +267,549 @@ impl Resource {
         }
     }
 
-    pub fn download(&self, dest: &Path, tool_name: String) -> Result<()> {
+    /// Download the resource into `dest`
+    pub fn download(&self, dest: &Path, opts: &DownloadOptions) -> Result<()> {
+        let sha256 = opts.sha256.as_deref();
         if !dest.exists() {
             fs::create_dir_all(dest).with(dest)?;
         }
         if !dest.is_dir() {
-            return Err(io::Error::new(io::ErrorKind::Other, "Not a directory")).with(dest);
+            return Err(io::Error::other("Not a directory")).with(dest);
         }
 
         match self {
-            Resource::Svn { url, .. } => Command::new("svn")
-                .args(["co", url.as_str(), "-r", "HEAD"])
-                .arg(dest)
-                .check_run()?,
-            Resource::Git { url, branch } => {
-                info!("Git clone {}", url);
-                let mut git = Command::new("git");
-                git.args(["clone", url.as_str(), "-q", "--depth", "1"])
-                    .arg(dest);
-                if let Some(branch) = branch {
-                    git.args(["-b", branch]);
+            Resource::Svn { url, .. } => {
+                check_online(url, opts.offline)?;
+                Command::new("svn")
+                    .args(["co", url.as_str(), "-r", "HEAD"])
+                    .arg(dest)
+                    .check_run()?
+            }
+            Resource::Git { url, reference } => {
+                if let Some(head) = git_checkout_revision(dest) {
+                    git_sparse_checkout(dest, "HEAD", &opts.exclude_dirs)?;
+                    if reference.as_ref().is_none_or(|reference| reference.is_checked_out(dest, &head)) {
+                        info!("Using checked out {}", head);
+                        return Ok(());
+                    }
+                    // Entry has been changed to another revision
+                    return self.update(dest, opts);
+                }
+                check_online(url, opts.offline)?;
+                match reference {
+                    Some(reference) => info!("Git fetch {} ({})", url, reference),
+                    None => info!("Git fetch {}", url),
+                }
+                Command::new("git")
+                    .args(["init", "-q"])
+                    .current_dir(dest)
+                    .check_run()?;
+                Command::new("git")
+                    .args(["remote", "add", "origin", url.as_str()])
+                    .current_dir(dest)
+                    .check_run()?;
+                if !opts.exclude_dirs.is_empty() {
+                    // Partial clone: blobs are fetched only for the files checked out
+                    let config = [
+                        ("remote.origin.promisor", "true"),
+                        ("remote.origin.partialclonefilter", "blob:none"),
+                    ];
+                    for (key, value) in &config {
+                        Command::new("git")
+                            .args(["config", key, value])
+                            .current_dir(dest)
+                            .check_run()?;
+                    }
                 }
-                git.check_run()?;
+                let rev = git_fetch(dest, url, reference.as_ref())?;
+                git_sparse_checkout(dest, &rev, &opts.exclude_dirs)?;
+                git_checkout(dest, reference.as_ref(), &rev)?;
+                info!("Checked out {}", rev);
             }
             Resource::Tar { url } => {
-                let filename = get_filename_from_url(url)?;
-                let cache_dir = cache_dir()?.join("cache");
+                // Extracted source is reused unless the archive or the way of extraction is changed
+                let marker = dest.join(EXTRACTED_MARKER);
+                let extracted = format!(
+                    "url={}\nstrip_components={}\nexclude_dirs={}\n",
+                    url,
+                    opts.strip_components,
+                    opts.exclude_dirs.join(",")
+                );
+                if fs::read_to_string(&marker).is_ok_and(|content| content == extracted) {
+                    info!("Using extracted source: {}", dest.display());
+                    return Ok(());
+                }
+                let tar_file = match local_path(url) {
+                    Some(tar_file) => {
+                        info!("Using local tar file: {}", tar_file.display());
+                        if let Some(expected) = sha256 {
+                            verify_sha256(&tar_file, expected)?;
+                        }
+                        tar_file
+                    }
+                    None => fetch_tarball(url, sha256, &opts.mirrors, opts.offline)?,
+                };
+                archive::extract(&tar_file, dest, opts.strip_components, &opts.exclude_dirs)?;
+                fs::write(&marker, extracted).with(&marker)?;
+            }
+        }
+        Ok(())
+    }
 
-                if !cache_dir.exists() {
-                    fs::create_dir_all(&cache_dir).with(&cache_dir)?;
+    /// Update the resource checked out into `dest`. `opts` is used if it is not checked out yet.
+    pub fn update(&self, dest: &Path, opts: &DownloadOptions) -> Result<()> {
+        match self {
+            Resource::Svn { url } => {
+                check_online(url, opts.offline)?;
+                Command::new("svn")
+                    .arg("update")
+                    .current_dir(dest)
+                    .check_run()?
+            }
+            Resource::Git { url, reference } => {
+                let old = match git_checkout_revision(dest) {
+                    Some(old) => old,
+                    // Not checked out yet, e.g. with `--skip`
+                    None => return self.download(dest, opts),
+                };
+                check_online(url, opts.offline)?;
+                let new = git_fetch(dest, url, reference.as_ref())?;
+                if old == new {
+                    info!("Already up to date: {}", new);
+                } else {
+                    git_checkout(dest, reference.as_ref(), &new)?;
+                    info!("Updated {}: {} -> {}", url, old, new);
                 }
+            }
+            Resource::Tar { .. } => {}
+        }
+        Ok(())
+    }
 
-                let tar_file = cache_dir.join(&filename);
+    /// Path of the archive of tar resource, i.e. the local archive or the cached one
+    pub fn tarball(&self) -> Result<Option<PathBuf>> {
+        match self {
+            Resource::Tar { url } => match local_path(url) {
+                Some(path) => Ok(Some(path)),
+                None => Ok(Some(tarball_path(url)?)),
+            },
+            _ => Ok(None),
+        }
+    }
 
-                if tar_file.exists() {
-                    info!("Using cached tar file: {}", tar_file.display());
-                } else {
-                    info!("Downloading tar file: {}", url);
-                    let rt = tokio::runtime::Runtime::new()?;
-                    let bytes = rt.block_on(download(url))?;
+    /// Revision checked out into `dest`, i.e. commit hash for git and revision number for svn
+    pub fn revision(&self, dest: &Path) -> Option<String> {
+        match self {
+            Resource::Svn { .. } => Command::new("svn")
+                .args(["info", "--show-item", "revision"])
+                .current_dir(dest)
+                .check_output()
+                .ok()
+                .map(|(stdout, _)| stdout.trim().to_string()),
+            Resource::Git { .. } => git_checkout_revision(dest),
+            Resource::Tar { .. } => None,
+        }
+    }
+}
 
-                    drop(rt);
+/// Shallow fetch of the revision into the repository `dest`, and returns its commit hash
+fn git_fetch(dest: &Path, url: &str, reference: Option<&GitRef>) -> Result<String> {
+    // Fetch through the remote to apply the filter of partial clone
+    Command::new("git")
+        .args(["remote", "set-url", "origin", url])
+        .current_dir(dest)
+        .check_run()?;
+    let refspec = reference.map_or("HEAD".to_string(), |reference| reference.refspec());
+    // A commit is fetched by its full SHA, which GitHub and GitLab allow for any reachable commit
+    Command::new("git")
+        .args(["fetch", "-q", "--depth", "1", "origin"])
+        .arg(&refspec)
+        .current_dir(dest)
+        .check_run()?;
+    git_resolve(dest, "FETCH_HEAD").ok_or_else(|| Error::GitRevisionNotFound {
+        url: url.into(),
+        rev: refspec,
+    })
+}
 
-                    fs::write(&tar_file, &bytes)?;
+/// Commit hash of a revision in the repository `dir`
+fn git_resolve(dir: &Path, rev: &str) -> Option<String> {
+    Command::new("git")
+        .args(["rev-parse", "--verify", "-q"])
+        .arg(format!("{}^{{commit}}", rev))
+        .current_dir(dir)
+        .check_output()
+        .ok()
+        .map(|(stdout, _)| stdout.trim().to_string())
+}
 
-                    info!("Tar file cached: {}", tar_file.display());
-                }
+/// Check out only the top-level directories of `rev` except `exclude_dirs`, or everything if nothing is excluded
+/// or `rev` is not llvm-project monorepo
+fn git_sparse_checkout(dest: &Path, rev: &str, exclude_dirs: &[String]) -> Result<()> {
+    let (stdout, _) = Command::new("git")
+        .args(["ls-tree", "-d", "--name-only", rev])
+        .current_dir(dest)
+        .check_output()?;
+    let dirs: Vec<&str> = stdout.lines().collect();
+    // Directories are excluded only from llvm-project monorepo, whose LLVM is in `llvm/`
+    let exclude_dirs = if dirs.contains(&"llvm") { exclude_dirs } else { &[] };
+    let include: Vec<&str> = dirs
+        .iter()
+        .filter(|dir| !exclude_dirs.iter().any(|excluded| excluded == *dir))
+        .cloned()
+        .collect();
 
-                let tar_gz = File::open(
-                    &tar_file
-                )?;
-                let tar = GzDecoder::new(tar_gz);
-                let mut archive = Archive::new(tar);
-                let entries = archive
-                    .entries()
-                    .expect("Tar archive does not contain entries");
-
-                let bar = ProgressBar::new_spinner();
-                bar.set_style(
-                    ProgressStyle::default_spinner()
-                        .template("{spinner:.green} [{elapsed_precise}] Unpacking: {msg} [{pos}]")
-                        .expect("Invalid template")
-                );
+    if include.len() < dirs.len() {
+        debug!("Sparse checkout: {}", include.join(" "));
+        Command::new("git")
+            .args(["sparse-checkout", "set", "--cone"])
+            .args(&include)
+            .current_dir(dest)
+            .check_run()?;
+    } else if is_sparse_checkout(dest) {
+        Command::new("git")
+            .args(["sparse-checkout", "disable"])
+            .current_dir(dest)
+            .check_run()?;
+    }
+    Ok(())
+}
 
-                for entry in entries {
-                    let mut entry = entry.expect("Invalid tar entry");
-                    let path = entry.path().expect("Invalid unicode in filename");
+fn is_sparse_checkout(dest: &Path) -> bool {
+    Command::new("git")
+        .args(["config", "--bool", "core.sparseCheckout"])
+        .current_dir(dest)
+        .check_output()
+        .is_ok_and(|(stdout, _)| stdout.trim() == "true")
+}
 
-                    bar.set_message(path.to_string_lossy().to_string());
+/// Check out the fetched commit. A branch is checked out as a local branch, and others as detached HEAD.
+fn git_checkout(dest: &Path, reference: Option<&GitRef>, rev: &str) -> Result<()> {
+    let mut git = Command::new("git");
+    git.args(["checkout", "-q"]);
+    match reference {
+        Some(GitRef::Branch(branch)) => git.args(["-B", branch.as_str(), rev]),
+        _ => git.args(["--detach", rev]),
+    };
+    git.current_dir(dest).check_run()
+}
 
-                    let mut target = dest.to_owned().join(&tool_name);
-                    for comp in path.components().skip(1) {
-                        target = target.join(comp);
-                    }
-                    if let Err(e) = entry.unpack(&target) {
-                        match e.kind() {
-                            io::ErrorKind::AlreadyExists => debug!("{:?}", e.to_string()),
-                            _ => warn!("{:?}", e.to_string()),
-                        }
-                    }
+/// Commit hash of HEAD if `dir` is in a git repository
+pub fn git_revision(dir: &Path) -> Option<String> {
+    Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .current_dir(dir)
+        .check_output()
+        .ok()
+        .map(|(stdout, _)| stdout.trim().to_string())
+}
 
-                    bar.inc(1);
-                }
+/// Commit hash of HEAD if `dir` is the top of a git repository.
+///
+/// Different from [git_revision], this ignores enclosing repositories, e.g. the LLVM checkout around `tools/clang`.
+fn git_checkout_revision(dir: &Path) -> Option<String> {
+    if dir.join(".git").exists() {
+        git_revision(dir)
+    } else {
+        None
+    }
+}
+
+/// SHA-256 checksum of a file as lower-case hex
+pub fn sha256(path: &Path) -> Result<String> {
+    let mut file = File::open(path).with(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher).with(path)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Check SHA-256 checksum of a file
+pub fn verify_sha256(path: &Path, expected: &str) -> Result<()> {
+    let actual = sha256(path)?;
+    if !actual.eq_ignore_ascii_case(expected) {
+        return Err(Error::ChecksumMismatch {
+            path: path.into(),
+            expected: expected.to_lowercase(),
+            actual,
+        });
+    }
+    Ok(())
+}
+
+/// Directory where downloaded tarballs are cached
+pub fn tarball_cache_dir() -> Result<PathBuf> {
+    Ok(cache_dir()?.join("cache"))
+}
 
-                bar.finish_with_message("Unpacking completed");
+/// Cache path of the tarball if URL points a remote tar archive. Different from `Resource::from_url`,
+/// this never accesses the network.
+pub fn cached_tarball(url: &str) -> Result<Option<PathBuf>> {
+    if local_path(url).is_some() {
+        return Ok(None);
+    }
+    match get_filename_from_url(url) {
+        Ok(filename) if ARCHIVE_EXTENSIONS.iter().any(|ext| filename.ends_with(ext)) => {
+            Ok(Some(tarball_cache_dir()?.join(filename)))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn tarball_path(url: &str) -> Result<PathBuf> {
+    Ok(tarball_cache_dir()?.join(get_filename_from_url(url)?))
+}
+
+/// Path of the cached tarball of `url`, which is downloaded if not cached yet
+///
+/// `mirrors` are tried in order before `url`, and the next one is tried if a mirror fails.
+fn fetch_tarball(url: &str, sha256: Option<&str>, mirrors: &[String], offline: bool) -> Result<PathBuf> {
+    let tar_file = tarball_path(url)?;
+    let cache_dir = tar_file.parent().unwrap();
+
+    if !cache_dir.exists() {
+        fs::create_dir_all(cache_dir).with(cache_dir)?;
+    }
+
+    if tar_file.exists() {
+        match sha256.map(|expected| verify_sha256(&tar_file, expected)) {
+            Some(Err(Error::ChecksumMismatch { .. })) => {
+                warn!("Discard cached tar file with wrong checksum: {}", tar_file.display());
+                fs::remove_file(&tar_file).with(&tar_file)?;
+            }
+            Some(Err(e)) => return Err(e),
+            Some(Ok(())) | None => {
+                info!("Using cached tar file: {}", tar_file.display());
+                return Ok(tar_file);
             }
         }
-        Ok(())
     }
 
-    pub fn update(&self, dest: &Path) -> Result<()> {
-        match self {
-            Resource::Svn { .. } => Command::new("svn")
-                .arg("update")
-                .current_dir(dest)
-                .check_run()?,
-            Resource::Git { .. } => Command::new("git")
-                .arg("pull")
-                .current_dir(dest)
-                .check_run()?,
-            Resource::Tar { .. } => {}
+    let filename = get_filename_from_url(url)?;
+    let mut candidates: Vec<String> = mirrors
+        .iter()
+        .map(|mirror| format!("{}/{}", mirror.trim_end_matches('/'), filename))
+        .chain(std::iter::once(url.to_string()))
+        .collect();
+    if offline {
+        candidates.retain(|candidate| local_path(candidate).is_some());
+    }
+    if candidates.is_empty() {
+        return Err(Error::Offline { url: url.into() });
+    }
+
+    let last = candidates.len() - 1;
+    for (i, candidate) in candidates.iter().enumerate() {
+        info!("Downloading tar file: {}", candidate);
+        // A mirror is not retried, e.g. on connection refused, and the next one is tried immediately
+        let retries = if i < last { 0 } else { DOWNLOAD_RETRIES };
+        // An archive without known checksum is read through instead, not to cache a truncated one
+        let result = download(candidate, &tar_file, retries).and_then(|_| {
+            match sha256 {
+                Some(expected) => verify_sha256(&tar_file, expected),
+                None => archive::verify(&tar_file),
+            }
+            .inspect_err(|_| {
+                let _ = fs::remove_file(&tar_file);
+            })
+        });
+        match result {
+            Ok(()) => {
+                info!("Tar file cached: {}", tar_file.display());
+                return Ok(tar_file);
+            }
+            Err(e) if i < last && is_mirror_failure(&e, candidate) => {
+                warn!("{}", e);
+                warn!("Fall back to {}", candidates[i + 1]);
+                // Do not resume a partial file of another server
+                let part = part_path(&tar_file);
+                if part.exists() {
+                    fs::remove_file(&part).with(&part)?;
+                }
+            }
+            Err(e) => return Err(e),
         }
-        Ok(())
     }
+    unreachable!("the last candidate returns")
 }
 
-async fn download(url: &str) -> Result<Vec<u8>> {
-    let req = reqwest::get(url).await?;
-    let status = req.status();
-    if !status.is_success() {
-        return Err(Error::HttpError {
-            url: url.into(),
-            status,
-        });
+/// Errors of a candidate of [fetch_tarball] on which the next one is tried
+fn is_mirror_failure(e: &Error, candidate: &str) -> bool {
+    match e {
+        Error::HttpError { .. }
+        | Error::ReqwestError { .. }
+        | Error::ChecksumMismatch { .. }
+        | Error::BrokenArchive { .. } => true,
+        // Local mirror without the archive
+        Error::FileIo { source, .. } => {
+            source.kind() == io::ErrorKind::NotFound && local_path(candidate).is_some()
+        }
+        _ => false,
     }
+}
 
-    let content_length = req
-        .headers()
-        .get(reqwest::header::CONTENT_LENGTH)
-        .and_then(|len| len.to_str().ok()?.parse().ok())
-        .unwrap_or(0);
+/// Temporary file where `path` is downloaded into
+fn part_path(path: &Path) -> PathBuf {
+    let mut part = path.as_os_str().to_owned();
+    part.push(".part");
+    PathBuf::from(part)
+}
 
-    let bar = ProgressBar::new(content_length)
-        .with_style(
-            ProgressStyle::default_bar()
-                .template("{spinner:.green} [{elapsed_precise}] [{bar:38.cyan/blue}] {bytes}/{total_bytes} ({eta}) [{bytes_per_sec}]")
-                ?
-                .progress_chars("#>-")
-        );
+/// Download `url` into `path`.
+///
+/// The content is streamed into `<path>.part`, which is renamed to `path` when completed.
+/// Transient errors are retried `retries` times with backoff, resuming from the partial file by HTTP Range request.
+fn download(url: &str, path: &Path, retries: u32) -> Result<()> {
+    let part = part_path(path);
+    if let Some(local) = local_path(url) {
+        fs::copy(&local, &part).with(&local)?;
+        fs::rename(&part, path).with(path)?;
+        return Ok(());
+    }
 
-    let mut bytes: Vec<u8> = Vec::new();
-    let stream = block_on_stream(req.bytes_stream());
+    let bar = ProgressBar::new(0).with_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.green} [{elapsed_precise}] [{bar:38.cyan/blue}] {bytes}/{total_bytes} ({eta}) [{bytes_per_sec}]")?
+            .progress_chars("#>-"),
+    );
 
-    for chunk in stream {
-        let chunk = chunk.map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+    let rt = tokio::runtime::Runtime::new()?;
+    let mut retry = 0;
+    loop {
+        match rt.block_on(download_part(url, &part, &bar)) {
+            Ok(()) => break,
+            Err(e) if is_transient(&e) && retry < retries => {
+                let wait = Duration::from_secs(1 << retry);
+                retry += 1;
+                bar.suspend(|| {
+                    warn!("Download failed: {}", e);
+                    warn!("Retry ({}/{}) after {:?}", retry, retries, wait);
+                });
+                thread::sleep(wait);
+            }
+            Err(e) => {
+                bar.abandon();
+                return Err(e);
+            }
+        }
+    }
+    bar.finish();
+
+    fs::rename(&part, path).with(path)?;
+    Ok(())
+}
+
+/// Download the rest of `url` appending to `part`
+async fn download_part(url: &str, part: &Path, bar: &ProgressBar) -> Result<()> {
+    let offset = match fs::metadata(part) {
+        Ok(meta) => meta.len(),
+        Err(_) => 0,
+    };
+
+    let client = reqwest::Client::new();
+    let mut req = client.get(url);
+    if offset > 0 {
+        req = req.header(reqwest::header::RANGE, format!("bytes={}-", offset));
+    }
+    let mut res = req.send().await?;
+    let status = res.status();
+
+    let (mut file, offset) = match status {
+        reqwest::StatusCode::PARTIAL_CONTENT => {
+            debug!("Resume download from {} bytes", offset);
+            (fs::OpenOptions::new().append(true).open(part).with(part)?, offset)
+        }
+        // Range is not satisfiable, e.g. the server content has changed. Start over.
+        reqwest::StatusCode::RANGE_NOT_SATISFIABLE => {
+            fs::remove_file(part).with(part)?;
+            return Err(Error::HttpError {
+                url: url.into(),
+                status,
+            });
+        }
+        // Server may ignore Range header, and then the whole content is sent.
+        _ if status.is_success() => (File::create(part).with(part)?, 0),
+        _ => {
+            return Err(Error::HttpError {
+                url: url.into(),
+                status,
+            })
+        }
+    };
+
+    bar.set_length(offset + res.content_length().unwrap_or(0));
+    bar.set_position(offset);
+    while let Some(chunk) = res.chunk().await? {
+        file.write_all(&chunk).with(part)?;
         bar.inc(chunk.len() as u64);
-        bytes.extend_from_slice(&chunk);
     }
+    file.sync_all().with(part)?;
+    Ok(())
+}
 
-    bar.finish();
-    Ok(bytes)
+/// Errors which may succeed by retry
+fn is_transient(e: &Error) -> bool {
+    match e {
+        Error::ReqwestError { source } => {
+            source.is_timeout()
+                || source.is_connect()
+                || source.is_request()
+                || source.is_body()
+                || source.is_decode()
+        }
+        Error::HttpError { status, .. } => {
+            status.is_server_error()
+                || *status == reqwest::StatusCode::TOO_MANY_REQUESTS
+                || *status == reqwest::StatusCode::RANGE_NOT_SATISFIABLE
+        }
+        _ => false,
+    }
 }
 
+/// Whether network access is disabled by `CARGO_LLVM_OFFLINE`
+pub fn is_offline_env() -> bool {
+    env::var_os(OFFLINE_ENV).is_some_and(|v| !v.is_empty() && v != "0")
+}
+
+/// Fail if `url` needs network in offline mode
+fn check_online(url: &str, offline: bool) -> Result<()> {
+    if offline && local_path(url).is_none() {
+        return Err(Error::Offline { url: url.into() });
+    }
+    Ok(())
+}
+
+/// Local path of `file://` URL or a plain filesystem path
+fn local_path(url_str: &str) -> Option<PathBuf> {
+    match Url::parse(url_str) {
+        Ok(url) if url.scheme() == "file" => url.to_file_path().ok(),
+        // Drive letter of Windows path, e.g. `C:\llvm.tar.xz`, is parsed as a scheme
+        Ok(url) if url.scheme().len() == 1 => Some(PathBuf::from(url_str)),
+        Ok(_) => None,
+        Err(_) => {
+            let path = PathBuf::from(shellexpand::tilde(url_str).as_ref());
+            Some(fs::canonicalize(&path).unwrap_or(path))
+        }
+    }
+}
 
 fn get_filename_from_url(url_str: &str) -> Result<String> {
     let url = ::url::Url::parse(url_str).map_err(|_| Error::InvalidUrl {
         url: url_str.into(),
     })?;
-    let seg = url.path_segments().ok_or(Error::InvalidUrl {
+    let mut seg = url.path_segments().ok_or(Error::InvalidUrl {
         url: url_str.into(),
     })?;
-    let filename = seg.last().ok_or(Error::InvalidUrl {
+    let filename = seg.next_back().ok_or(Error::InvalidUrl {
         url: url_str.into(),
     })?;
     Ok(filename.to_string())
 }
 
-fn get_branch_from_url(url_str: &str) -> Result<Option<String>> {
+fn get_branch_from_url(url_str: &str) -> Result<Option<GitRef>> {
     let url = ::url::Url::parse(url_str).map_err(|_| Error::InvalidUrl {
         url: url_str.into(),
     })?;
-    Ok(url.fragment().map(ToOwned::to_owned))
+    Ok(url.fragment().map(|branch| GitRef::Branch(branch.into())))
 }
 
 fn strip_branch_from_url(url_str: &str) -> Result<String> {
@@ -330,3 +820,231 @@ fn strip_branch_from_url(url_str: &str) -> Result<String> {
     Ok(url.into())
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_url_local() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        let archive = dir.path().join("llvm-17.0.6.src.tar.xz");
+        fs::write(&archive, b"")?;
+        let file_url = Url::from_file_path(&archive).unwrap().to_string();
+
+        let expected = Resource::Tar { url: file_url.clone() };
+        assert_eq!(Resource::from_url(archive.to_str().unwrap(), false)?, expected);
+        assert_eq!(Resource::from_url(&file_url, false)?, expected);
+        assert_eq!(expected.tarball()?, Some(archive.clone()));
+        assert_eq!(cached_tarball(&file_url)?, None);
+
+        // Directory without git repository, and string which is neither URL nor existing path
+        assert!(Resource::from_url(dir.path().to_str().unwrap(), false).is_err());
+        assert!(Resource::from_url("github.com/llvm/llvm-project", false).is_err());
+
+        let repo = dir.path().join("llvm-project");
+        git_repo(&repo, "README.md")?;
+        let mirror = Resource::from_url(repo.to_str().unwrap(), false)?;
+        assert!(matches!(mirror, Resource::Git { url, reference: None } if url.starts_with("file://")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_local_mirror_without_archive() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        let mirror = dir.path().join("llvmorg-17.0.2.tar.gz");
+        let e = download(mirror.to_str().unwrap(), &dir.path().join("cached.tar.gz"), 0).unwrap_err();
+        assert!(is_mirror_failure(&e, mirror.to_str().unwrap()));
+        let file_url = Url::from_file_path(&mirror).unwrap().to_string();
+        assert!(is_mirror_failure(&e, &file_url));
+        assert!(!is_mirror_failure(&e, "https://example.com/llvmorg-17.0.2.tar.gz"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_dead_mirror_not_retried() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        // Nothing listens on the port after the listener is dropped
+        let port = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+        let mirror = format!("http://127.0.0.1:{}/llvmorg-17.0.2.tar.gz", port);
+
+        let start = std::time::Instant::now();
+        let e = download(&mirror, &dir.path().join("cached.tar.gz"), 0).unwrap_err();
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert!(is_mirror_failure(&e, &mirror));
+        Ok(())
+    }
+
+    const CONTENT: &[u8] = b"0123456789";
+
+    /// Serve a request on localhost by `respond`, which returns the status and the body for the Range header.
+    /// Returns the URL and the Range header received.
+    fn serve(
+        respond: fn(Option<&str>) -> (u16, &'static [u8]),
+    ) -> Result<(String, thread::JoinHandle<Option<String>>)> {
+        use std::io::{BufRead, BufReader};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("http://{}/llvmorg-17.0.2.tar.gz", listener.local_addr()?);
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut range = None;
+            for line in BufReader::new(stream.try_clone().unwrap()).lines() {
+                let line = line.unwrap();
+                if line.is_empty() {
+                    break;
+                }
+                if let Some((key, value)) = line.split_once(':') {
+                    if key.eq_ignore_ascii_case("range") {
+                        range = Some(value.trim().to_string());
+                    }
+                }
+            }
+            let (status, body) = respond(range.as_deref());
+            write!(stream, "HTTP/1.1 {} X\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", status, body.len())
+                .unwrap();
+            stream.write_all(body).unwrap();
+            range
+        });
+        Ok((url, handle))
+    }
+
+    /// Download `url` into `part` which has the first 5 bytes of [CONTENT]
+    fn resume(url: &str, part: &Path) -> Result<()> {
+        fs::write(part, &CONTENT[..5])?;
+        tokio::runtime::Runtime::new()?.block_on(download_part(url, part, &ProgressBar::hidden()))
+    }
+
+    #[test]
+    fn test_resume_partial_content() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        let part = dir.path().join("llvmorg-17.0.2.tar.gz.part");
+        let (url, server) = serve(|range| match range {
+            Some("bytes=5-") => (206, &CONTENT[5..]),
+            _ => (200, CONTENT),
+        })?;
+        resume(&url, &part)?;
+        assert_eq!(server.join().unwrap().as_deref(), Some("bytes=5-"));
+        assert_eq!(fs::read(&part)?, CONTENT);
+        Ok(())
+    }
+
+    #[test]
+    fn test_resume_range_ignored() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        let part = dir.path().join("llvmorg-17.0.2.tar.gz.part");
+        // Whole content is sent, and the partial file is truncated
+        let (url, server) = serve(|_| (200, CONTENT))?;
+        resume(&url, &part)?;
+        assert_eq!(server.join().unwrap().as_deref(), Some("bytes=5-"));
+        assert_eq!(fs::read(&part)?, CONTENT);
+        Ok(())
+    }
+
+    #[test]
+    fn test_resume_range_not_satisfiable() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        let part = dir.path().join("llvmorg-17.0.2.tar.gz.part");
+        // Partial file is removed, and the retry starts over
+        let (url, server) = serve(|_| (416, b""))?;
+        let e = resume(&url, &part).unwrap_err();
+        assert_eq!(server.join().unwrap().as_deref(), Some("bytes=5-"));
+        assert!(matches!(e, Error::HttpError { status, .. } if status == reqwest::StatusCode::RANGE_NOT_SATISFIABLE));
+        assert!(is_transient(&e));
+        assert!(!part.exists());
+
+        let (url, server) = serve(|_| (200, CONTENT))?;
+        download(&url, &dir.path().join("llvmorg-17.0.2.tar.gz"), 0)?;
+        assert_eq!(server.join().unwrap(), None);
+        assert_eq!(fs::read(dir.path().join("llvmorg-17.0.2.tar.gz"))?, CONTENT);
+        Ok(())
+    }
+
+    /// Create a git repository at `dir` with a commit containing `file`, and returns its commit hash
+    fn git_repo(dir: &Path, file: &str) -> Result<String> {
+        fs::create_dir_all(dir.join(file).parent().unwrap())?;
+        fs::write(dir.join(file), b"")?;
+        for args in [
+            &["init", "-q"][..],
+            &["add", file],
+            &["-c", "user.name=test", "-c", "user.email=test@example.com", "commit", "-q", "-m", file],
+        ] {
+            Command::new("git").args(args).current_dir(dir).silent().check_run()?;
+        }
+        Ok(git_revision(dir).unwrap())
+    }
+
+    #[test]
+    fn test_git_tool_in_llvm_checkout() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        let tool = dir.path().join("clang");
+        let tool_head = git_repo(&tool, "clang.txt")?;
+        let llvm = dir.path().join("llvm");
+        let llvm_head = git_repo(&llvm, "llvm.txt")?;
+
+        let tool_dir = llvm.join("tools").join("clang");
+        assert_eq!(git_checkout_revision(&tool_dir), None);
+
+        let src = Resource::from_url(tool.to_str().unwrap(), false)?;
+        src.download(&tool_dir, &DownloadOptions::default())?;
+        assert!(tool_dir.join("clang.txt").exists());
+        assert_eq!(src.revision(&tool_dir), Some(tool_head.clone()));
+
+        src.update(&tool_dir, &DownloadOptions::default())?;
+        assert_eq!(src.revision(&tool_dir), Some(tool_head));
+        assert_eq!(git_revision(&llvm), Some(llvm_head));
+        assert!(llvm.join("llvm.txt").exists());
+        assert!(!llvm.join("clang.txt").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_git_split_repo_not_sparse() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        let repo = dir.path().join("llvm-mirror");
+        git_repo(&repo, "runtimes/CMakeLists.txt")?;
+
+        let dest = dir.path().join("llvm");
+        let opts = DownloadOptions {
+            exclude_dirs: vec!["runtimes".into()],
+            ..Default::default()
+        };
+        Resource::from_url(repo.to_str().unwrap(), false)?.download(&dest, &opts)?;
+        assert!(dest.join("runtimes/CMakeLists.txt").exists());
+        assert!(!is_sparse_checkout(&dest));
+        Ok(())
+    }
+
+    #[test]
+    fn test_git_update_not_checked_out() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        let repo = dir.path().join("llvm-project");
+        fs::create_dir_all(repo.join("lld"))?;
+        fs::write(repo.join("lld/CMakeLists.txt"), b"")?;
+        let head = git_repo(&repo, "llvm/CMakeLists.txt")?;
+        Command::new("git").args(["add", "lld"]).current_dir(&repo).silent().check_run()?;
+        Command::new("git")
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com", "commit", "-q", "-m", "lld"])
+            .current_dir(&repo)
+            .silent()
+            .check_run()?;
+        let head = git_revision(&repo).filter(|new| *new != head).unwrap();
+
+        // Checked out with the options of the caller, e.g. after `--skip`
+        let dest = dir.path().join("llvm");
+        let src = Resource::Git {
+            url: Url::from_file_path(&repo).unwrap().into(),
+            reference: Some(GitRef::Rev(head.clone())),
+        };
+        let opts = DownloadOptions {
+            exclude_dirs: vec!["lld".into()],
+            ..Default::default()
+        };
+        src.update(&dest, &opts)?;
+        assert_eq!(src.revision(&dest), Some(head));
+        assert!(dest.join("llvm/CMakeLists.txt").exists());
+        assert!(!dest.join("lld").exists());
+        assert!(is_sparse_checkout(&dest));
+        Ok(())
+    }
+}
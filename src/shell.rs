@@ -0,0 +1,104 @@
+//! Shell syntax for exporting environment variables
+
+use std::{env, path::Path, str::FromStr};
+
+use crate::error::*;
+
+/// Supported shells
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    PowerShell,
+}
+
+impl FromStr for Shell {
+    type Err = Error;
+
+    fn from_str(shell: &str) -> Result<Self> {
+        match shell.to_ascii_lowercase().as_str() {
+            "bash" | "sh" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            "powershell" | "pwsh" => Ok(Shell::PowerShell),
+            _ => Err(Error::UnsupportedShell {
+                shell: shell.into(),
+            }),
+        }
+    }
+}
+
+impl Shell {
+    /// Detect the shell from `$SHELL`, or fall back to bash (PowerShell on Windows)
+    pub fn detect() -> Self {
+        env::var("SHELL")
+            .ok()
+            .and_then(|shell| {
+                let name = Path::new(&shell).file_stem()?.to_str()?.to_string();
+                Shell::from_str(&name).ok()
+            })
+            .unwrap_or(if cfg!(windows) {
+                Shell::PowerShell
+            } else {
+                Shell::Bash
+            })
+    }
+
+    /// Statement setting an environment variable
+    pub fn export(&self, key: &str, value: &str) -> String {
+        match self {
+            Shell::Bash | Shell::Zsh => format!("export {}={}", key, quote_posix(value)),
+            Shell::Fish => {
+                if key == "PATH" {
+                    let paths: Vec<_> = env::split_paths(value)
+                        .map(|path| quote_fish(&path.to_string_lossy()))
+                        .collect();
+                    format!("set -gx PATH {}", paths.join(" "))
+                } else {
+                    format!("set -gx {} {}", key, quote_fish(value))
+                }
+            }
+            Shell::PowerShell => format!("$env:{} = {}", key, quote_powershell(value)),
+        }
+    }
+
+    /// Statement removing an environment variable
+    pub fn unset(&self, key: &str) -> String {
+        match self {
+            Shell::Bash | Shell::Zsh => format!("unset {}", key),
+            Shell::Fish => format!("set -e {}", key),
+            Shell::PowerShell => format!("Remove-Item Env:{} -ErrorAction SilentlyContinue", key),
+        }
+    }
+}
+
+fn quote_posix(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+fn quote_fish(value: &str) -> String {
+    format!("'{}'", value.replace('\\', r"\\").replace('\'', r"\'"))
+}
+
+fn quote_powershell(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_export() {
+        assert_eq!(
+            Shell::Bash.export("CC", "/opt/it's/clang"),
+            r"export CC='/opt/it'\''s/clang'"
+        );
+        assert_eq!(Shell::Fish.export("CC", "/opt/clang"), "set -gx CC '/opt/clang'");
+        assert_eq!(
+            Shell::PowerShell.export("CC", "C:\\it's\\clang"),
+            "$env:CC = 'C:\\it''s\\clang'"
+        );
+    }
+}